@@ -0,0 +1,379 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::decompile::Decompiler;
+use super::{IntCode, Instruction, Memory, OpCode, ParameterMode};
+
+// Translates a program image into a standalone Rust module. Reachable code is
+// split into basic blocks, each block becomes a method on the generated
+// `Machine` and a dispatch loop moves between them. Instructions that the
+// program overwrites through a constant address, and any address that isn't a
+// block leader, go through the small interpreter embedded in the module. A
+// write through the relative base that lands on native code switches the
+// machine to the interpreter for the rest of the run.
+
+const INTERPRETER: &str = r#"
+    fn read(&self, addr: i64) -> i64 {
+        if addr < 0 { 0 } else { self.mem.get(addr as usize).cloned().unwrap_or(0) }
+    }
+
+    fn write(&mut self, addr: i64, value: i64) {
+        let index = addr as usize;
+        if self.mem.len() <= index {
+            self.mem.resize(index + 1, 0);
+        }
+        self.mem[index] = value;
+        if is_code(addr) {
+            self.native = false;
+        }
+    }
+
+    fn mode(&self, label: i64, n: u32) -> i64 {
+        if n == 3 { label / 10000 } else { (label / 10_i64.pow(n + 1)) % 10 }
+    }
+
+    fn arg(&self, label: i64, n: u32) -> i64 {
+        let raw = self.read(self.pc as i64 + n as i64);
+        match self.mode(label, n) {
+            1 => raw,
+            2 => self.read(self.rb + raw),
+            _ => self.read(raw),
+        }
+    }
+
+    fn dest(&self, label: i64, n: u32) -> i64 {
+        let raw = self.read(self.pc as i64 + n as i64);
+        match self.mode(label, n) {
+            2 => self.rb + raw,
+            _ => raw,
+        }
+    }
+
+    fn interpret(&mut self, input: &mut Vec<i64>, output: &mut Vec<i64>) -> Option<usize> {
+        let label = self.read(self.pc as i64);
+        let pc = self.pc;
+
+        match label % 100 {
+            1 => { let v = self.arg(label, 1) + self.arg(label, 2); let d = self.dest(label, 3); self.write(d, v); Some(pc + 4) },
+            2 => { let v = self.arg(label, 1) * self.arg(label, 2); let d = self.dest(label, 3); self.write(d, v); Some(pc + 4) },
+            3 => { let v = input.remove(0); let d = self.dest(label, 1); self.write(d, v); Some(pc + 2) },
+            4 => { output.push(self.arg(label, 1)); Some(pc + 2) },
+            5 => if self.arg(label, 1) != 0 { Some(self.arg(label, 2) as usize) } else { Some(pc + 3) },
+            6 => if self.arg(label, 1) == 0 { Some(self.arg(label, 2) as usize) } else { Some(pc + 3) },
+            7 => { let v = (self.arg(label, 1) < self.arg(label, 2)) as i64; let d = self.dest(label, 3); self.write(d, v); Some(pc + 4) },
+            8 => { let v = (self.arg(label, 1) == self.arg(label, 2)) as i64; let d = self.dest(label, 3); self.write(d, v); Some(pc + 4) },
+            9 => { self.rb += self.arg(label, 1); Some(pc + 2) },
+            99 => None,
+            _ => panic!("Invalid OpCode: {} at position {}", label, pc),
+        }
+    }
+"#;
+
+pub struct Compiled {
+    pub source: String,
+    pub native: usize,
+    pub interpreted: usize,
+}
+
+fn operand(arg: i64, mode: &ParameterMode) -> String {
+    match mode {
+        ParameterMode::Immediate => format!("{}_i64", arg),
+        ParameterMode::Position => format!("self.read({})", arg),
+        ParameterMode::Relative => format!("self.read(self.rb + {})", arg),
+    }
+}
+
+fn destination(arg: i64, mode: &ParameterMode) -> String {
+    match mode {
+        ParameterMode::Relative => format!("self.rb + {}", arg),
+        _ => format!("{}", arg),
+    }
+}
+
+fn write_target(i: &Instruction) -> Option<(i64, &ParameterMode)> {
+    match i.op {
+        OpCode::Add | OpCode::Mul | OpCode::LessThan | OpCode::Equals => Some((i.args[2].unwrap(), &i.modes[2])),
+        OpCode::Input => Some((i.args[0].unwrap(), &i.modes[0])),
+        _ => None,
+    }
+}
+
+// Rust statements for one instruction, `next` is the address of the following one.
+fn translate(i: &Instruction, next: usize) -> Vec<String> {
+    let arg = |n: usize| operand(i.args[n].unwrap(), &i.modes[n]);
+    let mut lines = match i.op {
+        OpCode::Add => vec![format!("let v = {} + {};", arg(0), arg(1))],
+        OpCode::Mul => vec![format!("let v = {} * {};", arg(0), arg(1))],
+        OpCode::LessThan => vec![format!("let v = ({} < {}) as i64;", arg(0), arg(1))],
+        OpCode::Equals => vec![format!("let v = ({} == {}) as i64;", arg(0), arg(1))],
+        OpCode::Input => vec![String::from("let v = input.remove(0);")],
+        OpCode::Output => vec![format!("output.push({});", arg(0))],
+        OpCode::RelativeBase => vec![format!("self.rb += {};", arg(0))],
+        _ if is_jump(i) && jumps_always(i) => vec![format!("return Some({} as usize);", arg(1))],
+        OpCode::JumpIfTrue => vec![format!("if {} != 0 {{ return Some({} as usize); }}", arg(0), arg(1))],
+        OpCode::JumpIfFalse => vec![format!("if {} == 0 {{ return Some({} as usize); }}", arg(0), arg(1))],
+        _ => vec![String::from("return None;")],
+    };
+
+    if let Some((dst, mode)) = write_target(i) {
+        lines.push(format!("self.write({}, v);", destination(dst, mode)));
+        if *mode == ParameterMode::Relative {
+            lines.push(format!("if !self.native {{ return Some({}); }}", next));
+        }
+    }
+
+    lines
+}
+
+pub fn compile(program: &[i64]) -> Compiled {
+    let decompiler = Decompiler::new(program);
+    let code = decompiler.code();
+
+    // Instructions the program writes into through a constant address can't be native.
+    let mut modified = BTreeSet::new();
+    for i in code.values() {
+        if let Some((dst, ParameterMode::Position)) | Some((dst, ParameterMode::Immediate)) = write_target(i) {
+            if let Some((addr, target)) = code.range(..=(dst.max(0) as usize)).next_back() {
+                if (dst as usize) < addr + target.len {
+                    modified.insert(*addr);
+                }
+            }
+        }
+    }
+
+    let native = code.iter()
+        .filter(|(addr, _)| !modified.contains(*addr))
+        .collect::<BTreeMap<&usize, &Instruction>>();
+
+    // Blocks start after every jump, at every immediate jump target and wherever
+    // native code can't be reached by falling through from more native code.
+    let mut leaders = BTreeSet::new();
+    for (addr, i) in native.iter() {
+        match code.range(..**addr).next_back() {
+            Some((p, prev)) if p + prev.len == **addr && native.contains_key(p) && prev.op != OpCode::Halt => (),
+            _ => { leaders.insert(**addr); },
+        }
+        if is_jump(i) {
+            leaders.insert(**addr + i.len);
+            if i.modes[1] == ParameterMode::Immediate {
+                leaders.insert(i.args[1].unwrap().max(0) as usize);
+            }
+        }
+    }
+
+    let mut blocks = BTreeMap::<usize, Vec<String>>::new();
+    for leader in leaders.iter().filter(|l| native.contains_key(l)) {
+        let mut addr = *leader;
+        let mut body = Vec::new();
+
+        loop {
+            let i = native[&addr];
+            let next = addr + i.len;
+
+            body.extend(translate(i, next));
+
+            if i.op == OpCode::Halt || jumps_always(i) {
+                break;
+            }
+            if !native.contains_key(&next) || leaders.contains(&next) {
+                body.push(format!("Some({})", next));
+                break;
+            }
+            addr = next;
+        }
+
+        blocks.insert(*leader, body);
+    }
+
+    let mut ranges = Vec::new();
+    for (addr, i) in native.iter() {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == **addr => *end = **addr + i.len - 1,
+            _ => ranges.push((**addr, **addr + i.len - 1)),
+        }
+    }
+    let code_ranges = ranges.iter()
+        .map(|(s, e)| format!("{}..={}", s, e))
+        .collect::<Vec<String>>();
+
+    let mut source = format!("// Generated from a {} word Intcode image, do not edit.\n", program.len());
+    source.push_str("#![allow(dead_code, unreachable_code, unused_variables, clippy::all)]\n\n");
+    source.push_str(&format!(
+        "static IMAGE: [i64; {}] = [{}];\n\n",
+        program.len(),
+        program.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(", ")
+    ));
+    source.push_str("pub fn run(input: &mut Vec<i64>) -> Vec<i64> {\n");
+    source.push_str("    let mut machine = Machine::new();\n    let mut output = Vec::new();\n\n");
+    source.push_str("    machine.run(input, &mut output);\n\n    output\n}\n\n");
+    source.push_str("pub struct Machine {\n    pub mem: Vec<i64>,\n    pub pc: usize,\n    pub rb: i64,\n    pub native: bool,\n}\n\n");
+    source.push_str(&format!(
+        "fn is_code(addr: i64) -> bool {{\n    {}\n}}\n\n",
+        if code_ranges.is_empty() { String::from("false") } else { format!("matches!(addr, {})", code_ranges.join(" | ")) }
+    ));
+    source.push_str("impl Machine {\n");
+    source.push_str("    pub fn new() -> Self {\n        Machine { mem: IMAGE.to_vec(), pc: 0, rb: 0, native: true }\n    }\n\n");
+    source.push_str("    pub fn run(&mut self, input: &mut Vec<i64>, output: &mut Vec<i64>) {\n        loop {\n");
+    source.push_str("            let next = if !self.native {\n                self.interpret(input, output)\n            } else {\n                match self.pc {\n");
+    for leader in blocks.keys() {
+        source.push_str(&format!("                    {} => self.block_{}(input, output),\n", leader, leader));
+    }
+    source.push_str("                    _ => self.interpret(input, output),\n                }\n            };\n\n");
+    source.push_str("            match next {\n                Some(pc) => self.pc = pc,\n                None => return,\n            }\n        }\n    }\n");
+    source.push_str(INTERPRETER);
+
+    for (leader, body) in blocks.iter() {
+        source.push_str(&format!(
+            "\n    fn block_{}(&mut self, input: &mut Vec<i64>, output: &mut Vec<i64>) -> Option<usize> {{\n",
+            leader
+        ));
+        for line in body.iter() {
+            source.push_str(&format!("        {}\n", line));
+        }
+        source.push_str("    }\n");
+    }
+    source.push_str("}\n");
+
+    Compiled {
+        source,
+        native: native.len(),
+        interpreted: modified.len(),
+    }
+}
+
+fn is_jump(i: &Instruction) -> bool {
+    i.op == OpCode::JumpIfTrue || i.op == OpCode::JumpIfFalse
+}
+
+fn jumps_always(i: &Instruction) -> bool {
+    match (&i.op, &i.modes[0], i.args[0]) {
+        (OpCode::JumpIfTrue, ParameterMode::Immediate, Some(c)) => c != 0,
+        (OpCode::JumpIfFalse, ParameterMode::Immediate, Some(c)) => c == 0,
+        _ => false,
+    }
+}
+
+static BUILDS: AtomicUsize = AtomicUsize::new(0);
+
+// A scratch directory removed again however the build goes.
+struct BuildDir(PathBuf);
+
+impl BuildDir {
+    fn new() -> Result<Self, String> {
+        let dir = env::temp_dir().join(format!("intcode-aot-{}-{}", std::process::id(), BUILDS.fetch_add(1, Ordering::SeqCst)));
+
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        Ok(BuildDir(dir))
+    }
+}
+
+impl Drop for BuildDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Builds the generated module with rustc, runs it and compares its output with
+// `IntCode::run_program` given the same inputs.
+pub fn differential(program: &[i64], inputs: &[i64]) -> Result<Vec<i64>, String> {
+    let build_dir = BuildDir::new()?;
+    let dir = &build_dir.0;
+    let binary = dir.join("program");
+
+    fs::write(dir.join("program.rs"), compile(program).source).map_err(|e| e.to_string())?;
+    fs::write(dir.join("main.rs"), "mod program;\n\nfn main() {\n    \
+        let mut input = std::env::args().skip(1).map(|a| a.parse::<i64>().unwrap()).collect::<Vec<i64>>();\n    \
+        let output = program::run(&mut input);\n    \
+        println!(\"{}\", output.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(\",\"));\n}\n")
+        .map_err(|e| e.to_string())?;
+
+    let build = Command::new(env::var("RUSTC").unwrap_or_else(|_| String::from("rustc")))
+        .arg("--edition=2018")
+        .arg("-o").arg(&binary)
+        .arg(dir.join("main.rs"))
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !build.status.success() {
+        return Err(String::from_utf8_lossy(&build.stderr).into_owned());
+    }
+
+    let run = Command::new(&binary)
+        .args(inputs.iter().map(|v| v.to_string()))
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    let compiled = String::from_utf8_lossy(&run.stdout)
+        .trim()
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<i64>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<i64>, String>>()?;
+
+    let memory = Memory::new(program.to_vec());
+    let interpreted = IntCode::new(memory).run_program(&mut inputs.to_vec());
+
+    if compiled == interpreted {
+        Ok(compiled)
+    } else {
+        Err(format!("compiled output {:?} differs from interpreter {:?}", compiled, interpreted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_modified_instruction_is_interpreted() {
+        let program = vec![1101,5,0,5,104,1,99];
+        let compiled = compile(&program);
+
+        assert_eq!((compiled.native, compiled.interpreted), (2, 1));
+        assert_eq!(differential(&program, &[]), Ok(vec![5]));
+    }
+
+    #[test]
+    fn build_dir_is_removed() {
+        let dir = BuildDir::new().unwrap();
+        let path = dir.0.clone();
+
+        fs::write(path.join("main.rs"), "fn main() {").unwrap();
+        drop(dir);
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn quine_matches_interpreter() {
+        let program = vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
+
+        assert_eq!(differential(&program, &[]), Ok(program.clone()));
+    }
+
+    #[test]
+    fn calls_match_interpreter() {
+        let program = vec![109,50,21101,9,0,0,1105,1,10,99,104,42,2106,0,0];
+
+        assert_eq!(differential(&program, &[]), Ok(vec![42]));
+    }
+
+    #[test]
+    fn branches_match_interpreter() {
+        let program = vec![3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9];
+
+        assert_eq!(differential(&program, &[0]), Ok(vec![0]));
+        assert_eq!(differential(&program, &[7]), Ok(vec![1]));
+    }
+
+    #[test]
+    fn runtime_code_write_falls_back() {
+        // writes 99 over the output at 6 through the relative base
+        let program = vec![109,6,21101,99,0,0,104,7,99];
+
+        assert_eq!(differential(&program, &[]), Ok(vec![]));
+    }
+}
@@ -0,0 +1,192 @@
+// Constructors for generating programs from Rust. Each one checks its operands
+// the way `build` does, the binary itself only needs a few of them.
+#![allow(dead_code)]
+
+use super::{Instruction, OpCode, ParameterMode};
+
+// An instruction argument together with how it is addressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Immediate(i64),
+    Position(i64),
+    Relative(i64),
+}
+
+impl Operand {
+    pub fn mode(self) -> ParameterMode {
+        match self {
+            Operand::Immediate(_) => ParameterMode::Immediate,
+            Operand::Position(_) => ParameterMode::Position,
+            Operand::Relative(_) => ParameterMode::Relative,
+        }
+    }
+
+    pub fn raw(self) -> i64 {
+        match self {
+            Operand::Immediate(v) | Operand::Position(v) | Operand::Relative(v) => v,
+        }
+    }
+}
+
+impl Instruction {
+    // Rejects a wrong number of operands and a destination in immediate mode,
+    // which would otherwise encode something the VM can't run as written.
+    pub fn build(op: OpCode, operands: &[Operand]) -> Result<Self, String> {
+        let instruction = Instruction::make(op, operands);
+        let arity = instruction.len.saturating_sub(1);
+
+        if operands.len() != arity {
+            return Err(format!("`{}` takes {} operands, not {}", op, arity, operands.len()));
+        }
+
+        match instruction.destination() {
+            Some(n) if instruction.modes[n] == ParameterMode::Immediate => {
+                Err(format!("`{}` can't write through immediate operand {}", op, operands[n].raw()))
+            },
+            _ => Ok(instruction),
+        }
+    }
+
+    // Lays the operands out without checking them.
+    fn make(op: OpCode, operands: &[Operand]) -> Self {
+        let mut instruction = Instruction {
+            op,
+            len: Instruction::len(&op),
+            ..Default::default()
+        };
+
+        for (n, operand) in operands.iter().enumerate().take(instruction.len.saturating_sub(1)) {
+            instruction.args[n] = Some(operand.raw());
+            instruction.modes[n] = operand.mode();
+        }
+
+        instruction
+    }
+
+    pub fn add(src1: Operand, src2: Operand, dst: Operand) -> Result<Self, String> {
+        Instruction::build(OpCode::Add, &[src1, src2, dst])
+    }
+
+    pub fn mul(src1: Operand, src2: Operand, dst: Operand) -> Result<Self, String> {
+        Instruction::build(OpCode::Mul, &[src1, src2, dst])
+    }
+
+    pub fn input(dst: Operand) -> Result<Self, String> {
+        Instruction::build(OpCode::Input, &[dst])
+    }
+
+    pub fn output(src: Operand) -> Result<Self, String> {
+        Instruction::build(OpCode::Output, &[src])
+    }
+
+    pub fn jump_if_true(test: Operand, target: Operand) -> Result<Self, String> {
+        Instruction::build(OpCode::JumpIfTrue, &[test, target])
+    }
+
+    pub fn jump_if_false(test: Operand, target: Operand) -> Result<Self, String> {
+        Instruction::build(OpCode::JumpIfFalse, &[test, target])
+    }
+
+    pub fn less_than(src1: Operand, src2: Operand, dst: Operand) -> Result<Self, String> {
+        Instruction::build(OpCode::LessThan, &[src1, src2, dst])
+    }
+
+    pub fn equals(src1: Operand, src2: Operand, dst: Operand) -> Result<Self, String> {
+        Instruction::build(OpCode::Equals, &[src1, src2, dst])
+    }
+
+    pub fn relative_base(offset: Operand) -> Result<Self, String> {
+        Instruction::build(OpCode::RelativeBase, &[offset])
+    }
+
+    pub fn halt() -> Result<Self, String> {
+        Instruction::build(OpCode::Halt, &[])
+    }
+
+    pub fn operand(&self, n: usize) -> Option<Operand> {
+        let raw = self.args[n]?;
+
+        Some(match self.modes[n] {
+            ParameterMode::Immediate => Operand::Immediate(raw),
+            ParameterMode::Position => Operand::Position(raw),
+            ParameterMode::Relative => Operand::Relative(raw),
+        })
+    }
+
+    // Packs the opcode and modes back into a label, the inverse of `new`.
+    pub fn label(&self) -> i64 {
+        self.modes.iter().rev().fold(0, |label, m| label * 10 + m.code()) * 100 + self.op.code()
+    }
+
+    // The label followed by the arguments, as the instruction sits in memory.
+    pub fn encode(&self) -> Vec<i64> {
+        let mut words = vec![self.label()];
+
+        words.extend(self.args.iter().flatten());
+        words
+    }
+}
+
+pub fn assemble(instructions: &[Instruction]) -> Vec<i64> {
+    instructions.iter().flat_map(|i| i.encode()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::disasm::decode;
+    use super::super::{IntCode, Memory};
+    use self::Operand::*;
+
+    #[test]
+    fn builds_a_program() {
+        let program = assemble(&[
+            Instruction::input(Position(9)).unwrap(),
+            Instruction::equals(Position(9), Immediate(8), Relative(10)).unwrap(),
+            Instruction::output(Relative(10)).unwrap(),
+            Instruction::halt().unwrap(),
+        ]);
+
+        assert_eq!(program, vec![3, 9, 21008, 9, 8, 10, 204, 10, 99]);
+
+        let memory = Memory::new(program);
+
+        assert_eq!(IntCode::new(memory).run_program(&mut vec![8]), vec![1]);
+    }
+
+    #[test]
+    fn round_trips_through_decode() {
+        let operands = [Immediate(-7), Position(12), Relative(-3)];
+
+        for a in operands.iter() {
+            for b in operands.iter() {
+                for i in [
+                    Instruction::add(*a, *b, Relative(1)).unwrap(),
+                    Instruction::mul(*b, *a, Position(2)).unwrap(),
+                    Instruction::less_than(*a, *b, Position(3)).unwrap(),
+                    Instruction::equals(*a, *b, Relative(4)).unwrap(),
+                    Instruction::jump_if_true(*a, *b).unwrap(),
+                    Instruction::jump_if_false(*a, *b).unwrap(),
+                    Instruction::input(Relative(5)).unwrap(),
+                    Instruction::output(*b).unwrap(),
+                    Instruction::relative_base(*a).unwrap(),
+                    Instruction::halt().unwrap(),
+                ].iter() {
+                    assert_eq!(&decode(&i.encode(), 0), i);
+                }
+            }
+        }
+
+        assert_eq!(Instruction::add(Immediate(1), Position(2), Relative(3)).unwrap().operand(2), Some(Relative(3)));
+        assert_eq!(Instruction::halt().unwrap().operand(0), None);
+    }
+
+    #[test]
+    fn rejects_bad_operands() {
+        assert_eq!(Instruction::add(Position(1), Position(2), Immediate(3)).err().as_deref(), Some("`add` can't write through immediate operand 3"));
+        assert_eq!(Instruction::input(Immediate(7)).err().as_deref(), Some("`in` can't write through immediate operand 7"));
+        assert_eq!(Instruction::build(OpCode::Mul, &[Position(1)]).err().as_deref(), Some("`mul` takes 3 operands, not 1"));
+        assert_eq!(Instruction::build(OpCode::Halt, &[Position(1)]).err().as_deref(), Some("`hlt` takes 0 operands, not 1"));
+        assert!(Instruction::build(OpCode::JumpIfTrue, &[Immediate(1), Immediate(0)]).is_ok());
+    }
+}
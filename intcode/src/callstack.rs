@@ -0,0 +1,187 @@
+use std::fmt;
+
+use super::decompile::function_name;
+use super::observer::Observer;
+use super::{Instruction, OpCode};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub function: usize,
+    pub call_site: usize,
+    pub return_to: usize,
+    // Where the caller stored the return address.
+    pub slot: usize,
+    // The caller's relative base when it made the call.
+    pub base: usize,
+}
+
+// Reconstructs calls from the usual Intcode convention: the caller stores the
+// address after its jump somewhere and then jumps away, the callee moves the
+// relative base to make room for its frame and eventually jumps back to the
+// stored address. Jumps that don't fit the pattern are treated as plain control
+// flow, so hand written programs just show up as one long `main`.
+#[derive(Clone, Debug, Default)]
+pub struct CallStack {
+    pub frames: Vec<Frame>,
+    relative_base: usize,
+    last: Option<(usize, usize, bool)>,
+    recent: Vec<(usize, i64)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Location {
+    pub ic: usize,
+    pub function: usize,
+    pub relative_base: usize,
+}
+
+pub struct Backtrace(pub Vec<Location>);
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (n, l) in self.0.iter().enumerate() {
+            writeln!(f, "#{} {:>6} in {} (rb {})", n, l.ic, function_name(l.function), l.relative_base)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack::default()
+    }
+
+    fn jumped(&mut self, site: usize, len: usize, target: usize) {
+        if let Some(n) = self.frames.iter().rposition(|f| f.return_to == target) {
+            self.frames.truncate(n);
+            return;
+        }
+
+        let return_to = site + len;
+
+        if let Some((slot, _)) = self.recent.iter().rev().find(|(_, v)| *v == return_to as i64) {
+            self.frames.push(Frame {
+                function: target,
+                call_site: site,
+                return_to,
+                slot: *slot,
+                base: self.relative_base,
+            });
+        }
+    }
+
+    // Innermost first: where the machine is now, then every call site below it.
+    pub fn backtrace(&self, ic: usize) -> Backtrace {
+        let mut locations = vec![Location {
+            ic,
+            function: self.frames.last().map_or(0, |f| f.function),
+            relative_base: self.relative_base,
+        }];
+
+        for (n, frame) in self.frames.iter().enumerate().rev() {
+            locations.push(Location {
+                ic: frame.call_site,
+                function: if n == 0 { 0 } else { self.frames[n - 1].function },
+                relative_base: frame.base,
+            });
+        }
+
+        Backtrace(locations)
+    }
+}
+
+impl Observer for CallStack {
+    fn fetch(&mut self, addr: usize, instruction: &Instruction) {
+        if let Some((site, len, true)) = self.last.take() {
+            if addr != site + len {
+                self.jumped(site, len, addr);
+            }
+            self.recent.clear();
+        }
+
+        self.last = Some((addr, instruction.len, matches!(instruction.op, OpCode::JumpIfTrue | OpCode::JumpIfFalse)));
+    }
+
+    fn write(&mut self, addr: usize, _old: i64, new: i64) {
+        self.recent.push((addr, new));
+    }
+
+    fn relative_base(&mut self, base: usize) {
+        self.relative_base = base;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::debugger::Debugger;
+    use super::super::{IntCode, Memory};
+
+    // main calls f at 20, f calls g at 40, g reads input it doesn't have.
+    fn program() -> Vec<i64> {
+        let mut program = vec![0; 60];
+        let parts: [(usize, &[i64]); 3] = [
+            (0, &[109,100, 21101,0,9,0, 1105,1,20, 99]),
+            (20, &[109,5, 21101,0,29,0, 1105,1,40, 109,-5, 2105,1,0]),
+            (40, &[109,3, 203,0, 109,-3, 2105,1,0]),
+        ];
+
+        for (at, code) in parts.iter() {
+            program[*at..*at + code.len()].copy_from_slice(code);
+        }
+
+        program
+    }
+
+    #[test]
+    fn backtrace_through_calls() {
+        let mut intcode = IntCode::new(Memory::new(program()));
+        let stack = intcode.observe(CallStack::new());
+
+        assert!(intcode.try_run(&mut vec![], 1000).is_err());
+        assert_eq!(stack.borrow().backtrace(intcode.ic).to_string(), concat!(
+            "#0     42 in func_40 (rb 108)\n",
+            "#1     26 in func_20 (rb 105)\n",
+            "#2      6 in main (rb 100)\n",
+        ));
+    }
+
+    #[test]
+    fn execute_faults_point_at_the_instruction() {
+        let mut intcode = IntCode::new(Memory::new(vec![1101, 9223372036854775807, 1, 0, 1105, 1, 0]));
+        let stack = intcode.observe(CallStack::new());
+        let fault = intcode.try_run(&mut vec![], 1000).unwrap_err();
+
+        assert_eq!(intcode.ic, 4);
+        assert_eq!(stack.borrow().backtrace(intcode.fault_ic(&fault)).to_string(), "#0      0 in main (rb 0)\n");
+    }
+
+    #[test]
+    fn returns_pop_frames() {
+        let mut intcode = IntCode::new(Memory::new(program()));
+        let stack = intcode.observe(CallStack::new());
+
+        assert_eq!(intcode.try_run(&mut vec![1], 1000), Ok(vec![]));
+
+        let stack = stack.borrow();
+
+        assert!(stack.frames.is_empty());
+        assert_eq!(stack.backtrace(intcode.ic).0, vec![Location { ic: 9, function: 0, relative_base: 100 }]);
+    }
+
+    #[test]
+    fn stepping_back_restores_frames() {
+        let mut debugger = Debugger::new(IntCode::new(Memory::new(program())), vec![]);
+
+        debugger.run();
+        assert_eq!(debugger.calls.borrow().frames.len(), 2);
+
+        debugger.command("b 2");
+        assert_eq!(debugger.calls.borrow().frames.len(), 1);
+        assert_eq!(debugger.command("bt").unwrap(), concat!(
+            "#0     26 in func_20 (rb 105)\n",
+            "#1      6 in main (rb 100)\n",
+        ));
+    }
+}
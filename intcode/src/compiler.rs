@@ -0,0 +1,1078 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::builder::Operand::{Immediate, Position, Relative};
+use super::{Instruction, OpCode};
+
+// A small C-like language that compiles to Intcode:
+//
+//     var seen[10];
+//
+//     fn count(n) {
+//         var total = 0;
+//         while (n > 0) { total = total + n; n = n - 1; }
+//         return total;
+//     }
+//
+//     fn main() { output(count(input())); }
+//
+// Everything is an i64. Globals and global arrays live after the code, locals
+// and local arrays in a frame addressed through the relative base. `&&` and
+// `||` always evaluate both sides, and there is no division since Intcode has
+// none. Execution starts at `main`, the program halts when it returns.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// Longest first so `<=` isn't read as `<` followed by `=`.
+const SYMBOLS: [&str; 21] = [
+    "==", "!=", "<=", ">=", "&&", "||",
+    "+", "-", "*", "<", ">", "!", "=", "(", ")", "{", "}", "[", "]", ",", ";",
+];
+
+fn lex(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+
+    for (n, line) in text.lines().enumerate().map(|(n, l)| (n + 1, l)) {
+        let line = line.split("//").next().unwrap_or("");
+        let mut rest = line.trim_start();
+
+        while let Some(c) = rest.chars().next() {
+            let len = if c.is_ascii_digit() {
+                let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                let value = rest[..len].parse::<i64>().map_err(|_| format!("line {}: number too large", n))?;
+                tokens.push((Token::Number(value), n));
+                len
+            } else if c.is_alphabetic() || c == '_' {
+                let len = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+                tokens.push((Token::Name(rest[..len].to_string()), n));
+                len
+            } else {
+                let symbol = SYMBOLS.iter()
+                    .find(|s| rest.starts_with(*s))
+                    .ok_or_else(|| format!("line {}: unexpected {:?}", n, c))?;
+                tokens.push((Token::Symbol(symbol), n));
+                symbol.len()
+            };
+
+            rest = rest[len..].trim_start();
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Var(String),
+    Index(String, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+struct Decl {
+    name: String,
+    size: Option<usize>,
+    init: Option<Expr>,
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Var(Vec<Decl>),
+    Assign(String, Option<Expr>, Expr),
+    If(Expr, Block, Block),
+    While(Expr, Block),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+// Statements with the line they start on.
+type Block = Vec<(usize, Stmt)>;
+
+#[derive(Debug)]
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Block,
+    line: usize,
+}
+
+#[derive(Debug, Default)]
+struct Program {
+    globals: Vec<(usize, Decl)>,
+    functions: Vec<Function>,
+}
+
+// Arrays are laid out in full, so their size is kept to something sane.
+const MAX_ARRAY: i64 = 1_000_000;
+
+// Binding strength of each binary operator, loosest first.
+const PRECEDENCE: [&[&str]; 6] = [
+    &["||"],
+    &["&&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["+", "-"],
+    &["*"],
+];
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    at: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.tokens.get(self.at).or_else(|| self.tokens.last()).map_or(1, |(_, l)| *l)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("line {}: {}", self.line(), message))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at).map(|(t, _)| t)
+    }
+
+    // A symbol, or a name when checking for a keyword.
+    fn is(&self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(Token::Symbol(s)) => *s == symbol,
+            Some(Token::Name(n)) => n == symbol,
+            _ => false,
+        }
+    }
+
+    fn accept(&mut self, symbol: &str) -> bool {
+        let is = self.is(symbol);
+
+        if is {
+            self.at += 1;
+        }
+        is
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.accept(symbol) {
+            Ok(())
+        } else {
+            self.error(&format!("expected `{}`", symbol))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.peek().cloned() {
+            Some(Token::Name(name)) => {
+                self.at += 1;
+                Ok(name)
+            },
+            _ => self.error("expected a name"),
+        }
+    }
+
+    fn number(&mut self) -> Result<i64, String> {
+        let sign = if self.accept("-") { -1 } else { 1 };
+
+        match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                self.at += 1;
+                Ok(sign * n)
+            },
+            _ => self.error("expected a number"),
+        }
+    }
+
+    fn program(&mut self) -> Result<Program, String> {
+        let mut program = Program::default();
+
+        while self.peek().is_some() {
+            let line = self.line();
+
+            if self.accept("var") {
+                for decl in self.decls(true)? {
+                    program.globals.push((line, decl));
+                }
+            } else if self.accept("fn") {
+                let name = self.name()?;
+                let mut params = Vec::new();
+
+                self.expect("(")?;
+                while !self.accept(")") {
+                    if !params.is_empty() {
+                        self.expect(",")?;
+                    }
+                    params.push(self.name()?);
+                }
+
+                let body = self.block()?;
+                program.functions.push(Function { name, params, body, line });
+            } else {
+                return self.error("expected `var` or `fn`");
+            }
+        }
+
+        Ok(program)
+    }
+
+    // Globals only take a constant as their initial value.
+    fn decls(&mut self, global: bool) -> Result<Vec<Decl>, String> {
+        let mut decls = Vec::new();
+
+        loop {
+            let name = self.name()?;
+            let mut decl = Decl { name, size: None, init: None };
+
+            if self.accept("[") {
+                let size = self.number()?;
+
+                if !(1..=MAX_ARRAY).contains(&size) {
+                    return self.error(&format!("array size must be between 1 and {}", MAX_ARRAY));
+                }
+                decl.size = Some(size as usize);
+                self.expect("]")?;
+            } else if self.accept("=") {
+                decl.init = Some(if global { Expr::Number(self.number()?) } else { self.expr(0)? });
+            }
+
+            decls.push(decl);
+
+            if !self.accept(",") {
+                break;
+            }
+        }
+
+        self.expect(";")?;
+        Ok(decls)
+    }
+
+    fn block(&mut self) -> Result<Block, String> {
+        let mut block = Vec::new();
+
+        self.expect("{")?;
+        while !self.accept("}") {
+            if self.peek().is_none() {
+                return self.error("expected `}`");
+            }
+            block.push((self.line(), self.statement()?));
+        }
+
+        Ok(block)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, String> {
+        if self.accept("var") {
+            return Ok(Stmt::Var(self.decls(false)?));
+        }
+
+        if self.accept("if") {
+            self.expect("(")?;
+            let condition = self.expr(0)?;
+            self.expect(")")?;
+
+            let then = self.block()?;
+            let otherwise = if !self.accept("else") {
+                Vec::new()
+            } else if self.is("if") {
+                vec![(self.line(), self.statement()?)]
+            } else {
+                self.block()?
+            };
+
+            return Ok(Stmt::If(condition, then, otherwise));
+        }
+
+        if self.accept("while") {
+            self.expect("(")?;
+            let condition = self.expr(0)?;
+            self.expect(")")?;
+
+            return Ok(Stmt::While(condition, self.block()?));
+        }
+
+        if self.accept("return") {
+            let value = if self.is(";") { None } else { Some(self.expr(0)?) };
+            self.expect(";")?;
+
+            return Ok(Stmt::Return(value));
+        }
+
+        let expr = self.expr(0)?;
+
+        let stmt = if self.accept("=") {
+            let value = self.expr(0)?;
+
+            match expr {
+                Expr::Var(name) => Stmt::Assign(name, None, value),
+                Expr::Index(name, index) => Stmt::Assign(name, Some(*index), value),
+                _ => return self.error("can only assign to a variable or an array element"),
+            }
+        } else {
+            Stmt::Expr(expr)
+        };
+
+        self.expect(";")?;
+        Ok(stmt)
+    }
+
+    fn expr(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut left = self.expr(level + 1)?;
+
+        while let Some(op) = PRECEDENCE[level].iter().find(|op| self.is(op)) {
+            self.at += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.expr(level + 1)?));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        for op in ["-", "!"].iter() {
+            if self.accept(op) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+
+        match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                self.at += 1;
+                Ok(Expr::Number(n))
+            },
+            Some(Token::Name(name)) => {
+                self.at += 1;
+
+                if self.accept("[") {
+                    let index = self.expr(0)?;
+                    self.expect("]")?;
+                    Ok(Expr::Index(name, Box::new(index)))
+                } else if self.accept("(") {
+                    let mut args = Vec::new();
+                    while !self.accept(")") {
+                        if !args.is_empty() {
+                            self.expect(",")?;
+                        }
+                        args.push(self.expr(0)?);
+                    }
+                    Ok(Expr::Call(name, args))
+                } else {
+                    Ok(Expr::Var(name))
+                }
+            },
+            Some(Token::Symbol("(")) => {
+                self.at += 1;
+                let expr = self.expr(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            },
+            _ => self.error("expected an expression"),
+        }
+    }
+}
+
+// Where a value lives while generating code. Globals and labels don't have an
+// address until the whole program is laid out, `Next` is a cell in the frame
+// of the function being called and depends on the size of the current frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Value {
+    Const(i64),
+    Global(usize),
+    Local(i64),
+    Next(i64),
+    Label(usize),
+    AddressOf(usize),
+    FrameSize(i64),
+    Code(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Fixup {
+    Label(usize),
+    Data(usize),
+}
+
+#[derive(Clone, Copy)]
+struct Variable {
+    // Data offset for globals, frame slot for locals.
+    at: usize,
+    size: Option<usize>,
+}
+
+// Cells at the start of the data segment the generated code uses itself.
+const RET: usize = 0;
+const NEG: usize = 1;
+const VAL: usize = 2;
+
+#[derive(Default)]
+struct Codegen {
+    code: Vec<i64>,
+    data: Vec<i64>,
+    lines: BTreeMap<usize, usize>,
+    line: usize,
+    labels: Vec<Option<usize>>,
+    fixups: Vec<(usize, Fixup)>,
+    globals: HashMap<String, Variable>,
+    functions: HashMap<String, (usize, usize)>,
+    // State for the function being generated.
+    locals: HashMap<String, Variable>,
+    slots: usize,
+    temps: usize,
+    frame: usize,
+    frame_fixups: Vec<(usize, i64, i64)>,
+    // The first instruction the builder refused, reported once generation ends.
+    invalid: Option<String>,
+}
+
+fn fold(op: &str, a: i64, b: i64) -> Option<i64> {
+    Some(match op {
+        "+" => a.checked_add(b)?,
+        "-" => a.checked_sub(b)?,
+        "*" => a.checked_mul(b)?,
+        "<" => (a < b) as i64,
+        "<=" => (a <= b) as i64,
+        ">" => (a > b) as i64,
+        ">=" => (a >= b) as i64,
+        "==" => (a == b) as i64,
+        "!=" => (a != b) as i64,
+        "&&" => (a != 0 && b != 0) as i64,
+        "||" => (a != 0 || b != 0) as i64,
+        _ => return None,
+    })
+}
+
+fn has_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::Var(_) => false,
+        Expr::Call(_, _) => true,
+        Expr::Index(_, e) | Expr::Unary(_, e) => has_call(e),
+        Expr::Binary(_, a, b) => has_call(a) || has_call(b),
+    }
+}
+
+impl Codegen {
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("line {}: {}", self.line, message))
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    fn emit(&mut self, op: OpCode, args: &[Value]) {
+        let addr = self.code.len();
+        let mut operands = Vec::new();
+
+        for (n, arg) in args.iter().enumerate() {
+            let word = addr + 1 + n;
+
+            operands.push(match *arg {
+                Value::Const(v) => Immediate(v),
+                Value::Local(offset) => Relative(offset),
+                Value::Code(addr) => Position(addr as i64),
+                Value::Global(offset) => {
+                    self.fixups.push((word, Fixup::Data(offset)));
+                    Position(0)
+                },
+                Value::AddressOf(offset) => {
+                    self.fixups.push((word, Fixup::Data(offset)));
+                    Immediate(0)
+                },
+                Value::Label(label) => {
+                    self.fixups.push((word, Fixup::Label(label)));
+                    Immediate(0)
+                },
+                Value::Next(offset) => {
+                    self.frame_fixups.push((word, 1, offset));
+                    Relative(0)
+                },
+                Value::FrameSize(sign) => {
+                    self.frame_fixups.push((word, sign, 0));
+                    Immediate(0)
+                },
+            });
+        }
+
+        self.lines.insert(addr, self.line);
+        match Instruction::build(op, &operands) {
+            Ok(instruction) => self.code.extend(instruction.encode()),
+            Err(e) => {
+                self.invalid.get_or_insert(format!("line {}: {}", self.line, e));
+            },
+        }
+    }
+
+    fn copy(&mut self, from: Value, to: Value) {
+        self.emit(OpCode::Add, &[from, Value::Const(0), to]);
+    }
+
+    fn temp(&mut self) -> Value {
+        let slot = self.slots + self.temps;
+
+        self.temps += 1;
+        self.frame = self.frame.max(slot + 1);
+        Value::Local(slot as i64)
+    }
+
+    fn variable(&self, name: &str) -> Result<(Value, Option<usize>), String> {
+        if let Some(v) = self.locals.get(name) {
+            return Ok((Value::Local(v.at as i64), v.size));
+        }
+
+        match self.globals.get(name) {
+            Some(v) => Ok((Value::Global(v.at), v.size)),
+            None => self.error(&format!("unknown variable `{}`", name)),
+        }
+    }
+
+    fn scalar(&self, name: &str) -> Result<Value, String> {
+        match self.variable(name)? {
+            (value, None) => Ok(value),
+            _ => self.error(&format!("`{}` is an array", name)),
+        }
+    }
+
+    fn array(&self, name: &str) -> Result<Value, String> {
+        match self.variable(name)? {
+            (value, Some(_)) => Ok(value),
+            _ => self.error(&format!("`{}` is not an array", name)),
+        }
+    }
+
+    // Global elements are reached by patching the address into the next
+    // instruction, local ones by moving the relative base by the index and
+    // back again. `value` is stored when given, otherwise the element is read.
+    fn element(&mut self, array: Value, index: Value, value: Option<Value>) -> Value {
+        let fixed = match (array, index) {
+            (Value::Global(at), Value::Const(i)) => (at as i64).checked_add(i)
+                .filter(|a| *a >= 0)
+                .map(|a| Value::Global(a as usize)),
+            (Value::Local(at), Value::Const(i)) => at.checked_add(i).map(Value::Local),
+            _ => None,
+        };
+
+        match (fixed, value) {
+            (Some(target), Some(value)) => {
+                self.copy(value, target);
+                return Value::Const(0);
+            },
+            (Some(target), None) => return target,
+            _ => (),
+        }
+
+        let result = match value {
+            Some(_) => Value::Const(0),
+            None => self.temp(),
+        };
+
+        match array {
+            Value::Global(at) => {
+                let here = self.code.len();
+
+                match value {
+                    Some(value) => {
+                        self.emit(OpCode::Add, &[Value::AddressOf(at), index, Value::Code(here + 7)]);
+                        self.emit(OpCode::Add, &[value, Value::Const(0), Value::Code(0)]);
+                    },
+                    None => {
+                        self.emit(OpCode::Add, &[Value::AddressOf(at), index, Value::Code(here + 5)]);
+                        self.emit(OpCode::Add, &[Value::Code(0), Value::Const(0), result]);
+                    },
+                }
+            },
+            _ => {
+                if let Some(value) = value {
+                    self.copy(value, Value::Global(VAL));
+                }
+
+                self.emit(OpCode::Mul, &[index, Value::Const(-1), Value::Global(NEG)]);
+                self.emit(OpCode::RelativeBase, &[index]);
+
+                match value {
+                    Some(_) => self.copy(Value::Global(VAL), array),
+                    None => self.copy(array, Value::Global(VAL)),
+                }
+
+                self.emit(OpCode::RelativeBase, &[Value::Global(NEG)]);
+
+                if value.is_none() {
+                    self.copy(Value::Global(VAL), result);
+                }
+            },
+        }
+
+        result
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr]) -> Result<Value, String> {
+        match (name, args.len()) {
+            ("input", 0) => {
+                let t = self.temp();
+                self.emit(OpCode::Input, &[t]);
+                return Ok(t);
+            },
+            ("output", 1) => {
+                let value = self.expr(&args[0])?;
+                self.emit(OpCode::Output, &[value]);
+                return Ok(Value::Const(0));
+            },
+            ("input", _) | ("output", _) => return self.error(&format!("wrong number of arguments to `{}`", name)),
+            _ => (),
+        }
+
+        let (label, arity) = match self.functions.get(name) {
+            Some(f) => *f,
+            None => return self.error(&format!("unknown function `{}`", name)),
+        };
+
+        if arity != args.len() {
+            return self.error(&format!("`{}` takes {} arguments, not {}", name, arity, args.len()));
+        }
+
+        // Everything is evaluated before anything is stored in the new frame,
+        // a call among the arguments would use the same cells.
+        let mut values = Vec::new();
+        for arg in args.iter() {
+            let value = self.expr(arg)?;
+            values.push(match value {
+                Value::Const(_) => value,
+                _ => {
+                    let t = self.temp();
+                    self.copy(value, t);
+                    t
+                },
+            });
+        }
+
+        for (n, value) in values.into_iter().enumerate() {
+            self.copy(value, Value::Next(n as i64 + 1));
+        }
+
+        let back = self.label();
+
+        self.copy(Value::Label(back), Value::Next(0));
+        self.emit(OpCode::RelativeBase, &[Value::FrameSize(1)]);
+        self.emit(OpCode::JumpIfTrue, &[Value::Const(1), Value::Label(label)]);
+        self.place(back);
+        self.emit(OpCode::RelativeBase, &[Value::FrameSize(-1)]);
+
+        let t = self.temp();
+        self.copy(Value::Global(RET), t);
+        Ok(t)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<Value, String> {
+        Ok(match expr {
+            Expr::Number(n) => Value::Const(*n),
+            Expr::Var(name) => self.scalar(name)?,
+            Expr::Index(name, index) => {
+                let array = self.array(name)?;
+                let index = self.expr(index)?;
+                self.element(array, index, None)
+            },
+            Expr::Call(name, args) => self.call(name, args)?,
+            Expr::Unary(op, e) => {
+                let value = self.expr(e)?;
+
+                // The most negative number has no negation, the machine is
+                // left to overflow on it.
+                match (*op, value) {
+                    ("-", Value::Const(v)) if v.checked_neg().is_some() => Value::Const(-v),
+                    ("!", Value::Const(v)) => Value::Const((v == 0) as i64),
+                    ("-", _) => self.binary(OpCode::Mul, value, Value::Const(-1)),
+                    _ => self.binary(OpCode::Equals, value, Value::Const(0)),
+                }
+            },
+            Expr::Binary(op, a, b) => {
+                let mut left = self.expr(a)?;
+
+                // A call on the right could change what the left refers to.
+                if has_call(b) && !matches!(left, Value::Const(_)) {
+                    let t = self.temp();
+                    self.copy(left, t);
+                    left = t;
+                }
+
+                let right = self.expr(b)?;
+
+                if let (Value::Const(x), Value::Const(y)) = (left, right) {
+                    if let Some(v) = fold(op, x, y) {
+                        return Ok(Value::Const(v));
+                    }
+                }
+
+                match *op {
+                    "+" => self.binary(OpCode::Add, left, right),
+                    "*" => self.binary(OpCode::Mul, left, right),
+                    "-" => {
+                        let negated = match right {
+                            Value::Const(v) if v.checked_neg().is_some() => Value::Const(-v),
+                            _ => self.binary(OpCode::Mul, right, Value::Const(-1)),
+                        };
+                        self.binary(OpCode::Add, left, negated)
+                    },
+                    "<" => self.binary(OpCode::LessThan, left, right),
+                    ">" => self.binary(OpCode::LessThan, right, left),
+                    "==" => self.binary(OpCode::Equals, left, right),
+                    "<=" | ">=" | "!=" => {
+                        let value = match *op {
+                            "<=" => self.binary(OpCode::LessThan, right, left),
+                            ">=" => self.binary(OpCode::LessThan, left, right),
+                            _ => self.binary(OpCode::Equals, left, right),
+                        };
+                        self.binary(OpCode::Equals, value, Value::Const(0))
+                    },
+                    _ => {
+                        // Both sides as 1 for zero and 0 otherwise: `&&` needs the sum to be
+                        // 0, `||` needs the product to be.
+                        let x = self.binary(OpCode::Equals, left, Value::Const(0));
+                        let y = self.binary(OpCode::Equals, right, Value::Const(0));
+                        let combined = if *op == "&&" {
+                            self.binary(OpCode::Add, x, y)
+                        } else {
+                            self.binary(OpCode::Mul, x, y)
+                        };
+                        self.binary(OpCode::Equals, combined, Value::Const(0))
+                    },
+                }
+            },
+        })
+    }
+
+    fn binary(&mut self, op: OpCode, left: Value, right: Value) -> Value {
+        let t = self.temp();
+
+        self.emit(op, &[left, right, t]);
+        t
+    }
+
+    fn statement(&mut self, line: usize, stmt: &Stmt) -> Result<(), String> {
+        self.line = line;
+        self.temps = 0;
+
+        match stmt {
+            Stmt::Var(decls) => {
+                for decl in decls.iter() {
+                    if self.locals.contains_key(&decl.name) {
+                        return self.error(&format!("`{}` is already declared", decl.name));
+                    }
+
+                    self.locals.insert(decl.name.clone(), Variable { at: self.slots, size: decl.size });
+                    self.slots = match self.slots.checked_add(decl.size.unwrap_or(1)) {
+                        Some(slots) => slots,
+                        None => return self.error("the frame is too large"),
+                    };
+                    self.frame = self.frame.max(self.slots);
+
+                    if let Some(init) = decl.init.as_ref() {
+                        let value = self.expr(init)?;
+                        let local = self.scalar(&decl.name)?;
+                        self.copy(value, local);
+                    }
+                }
+            },
+            Stmt::Assign(name, index, value) => {
+                let value = self.expr(value)?;
+
+                match index {
+                    None => {
+                        let target = self.scalar(name)?;
+                        self.copy(value, target);
+                    },
+                    Some(index) => {
+                        let array = self.array(name)?;
+                        let index = self.expr(index)?;
+                        self.element(array, index, Some(value));
+                    },
+                }
+            },
+            Stmt::If(condition, then, otherwise) => {
+                let (skip, end) = (self.label(), self.label());
+                let condition = self.expr(condition)?;
+
+                self.emit(OpCode::JumpIfFalse, &[condition, Value::Label(skip)]);
+                self.block(then)?;
+                if !otherwise.is_empty() {
+                    self.emit(OpCode::JumpIfTrue, &[Value::Const(1), Value::Label(end)]);
+                }
+                self.place(skip);
+                self.block(otherwise)?;
+                self.place(end);
+            },
+            Stmt::While(condition, body) => {
+                let (top, end) = (self.label(), self.label());
+
+                self.place(top);
+                let condition = self.expr(condition)?;
+                self.emit(OpCode::JumpIfFalse, &[condition, Value::Label(end)]);
+                self.block(body)?;
+                self.line = line;
+                self.emit(OpCode::JumpIfTrue, &[Value::Const(1), Value::Label(top)]);
+                self.place(end);
+            },
+            Stmt::Return(value) => {
+                let value = match value {
+                    Some(value) => self.expr(value)?,
+                    None => Value::Const(0),
+                };
+                self.ret(value);
+            },
+            Stmt::Expr(expr) => {
+                self.expr(expr)?;
+            },
+        }
+
+        Ok(())
+    }
+
+    fn block(&mut self, block: &Block) -> Result<(), String> {
+        for (line, stmt) in block.iter() {
+            self.statement(*line, stmt)?;
+        }
+
+        Ok(())
+    }
+
+    fn ret(&mut self, value: Value) {
+        self.copy(value, Value::Global(RET));
+        self.emit(OpCode::JumpIfTrue, &[Value::Const(1), Value::Local(0)]);
+    }
+
+    // The frame: return address at 0, then parameters, locals and temporaries.
+    fn function(&mut self, function: &Function) -> Result<(), String> {
+        self.line = function.line;
+        self.locals.clear();
+        self.frame_fixups.clear();
+
+        for (n, param) in function.params.iter().enumerate() {
+            if self.locals.insert(param.clone(), Variable { at: n + 1, size: None }).is_some() {
+                return self.error(&format!("parameter `{}` given twice", param));
+            }
+        }
+
+        self.slots = function.params.len() + 1;
+        self.frame = self.slots;
+
+        let label = self.functions[&function.name].0;
+        self.place(label);
+        self.block(&function.body)?;
+
+        self.line = function.line;
+        self.ret(Value::Const(0));
+
+        for (word, sign, offset) in self.frame_fixups.iter() {
+            self.code[*word] = sign * self.frame as i64 + offset;
+        }
+
+        Ok(())
+    }
+}
+
+// Source lines for each instruction the compiler emitted.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    pub lines: BTreeMap<usize, usize>,
+    // Where the code ends and the globals start.
+    pub end: usize,
+    pub functions: BTreeMap<usize, String>,
+    pub text: Vec<String>,
+}
+
+impl SourceMap {
+    // The line of the instruction at `addr` or of the one `addr` is part of.
+    pub fn line(&self, addr: usize) -> Option<usize> {
+        if addr >= self.end {
+            return None;
+        }
+
+        self.lines.range(..=addr).next_back().map(|(_, line)| *line)
+    }
+
+    pub fn describe(&self, addr: usize) -> Option<String> {
+        let line = self.line(addr)?;
+        let text = self.text.get(line - 1).map_or("", |t| t.trim());
+
+        Some(format!("line {}: {}", line, text))
+    }
+}
+
+pub struct Compiled {
+    pub image: Vec<i64>,
+    pub map: SourceMap,
+}
+
+pub fn compile(text: &str) -> Result<Compiled, String> {
+    let program = Parser { tokens: lex(text)?, at: 0 }.program()?;
+    let mut gen = Codegen {
+        data: vec![0; 3],
+        ..Default::default()
+    };
+
+    for (line, decl) in program.globals.iter() {
+        gen.line = *line;
+        if gen.globals.contains_key(&decl.name) {
+            return gen.error(&format!("`{}` is already declared", decl.name));
+        }
+
+        gen.globals.insert(decl.name.clone(), Variable { at: gen.data.len(), size: decl.size });
+        match decl.init {
+            Some(Expr::Number(n)) => gen.data.push(n),
+            _ => gen.data.extend(vec![0; decl.size.unwrap_or(1)]),
+        }
+    }
+
+    for function in program.functions.iter() {
+        let label = gen.label();
+
+        gen.line = function.line;
+        if gen.functions.insert(function.name.clone(), (label, function.params.len())).is_some() {
+            return gen.error(&format!("function `{}` is defined twice", function.name));
+        }
+    }
+
+    // Start the stack after the program and call main with a halt to return to.
+    let main = match program.functions.iter().find(|f| f.name == "main") {
+        Some(f) if f.params.is_empty() => {
+            gen.line = f.line;
+            gen.functions["main"].0
+        },
+        _ => return Err(String::from("missing `fn main()`")),
+    };
+    let (stack, halt) = (gen.label(), gen.label());
+
+    gen.emit(OpCode::RelativeBase, &[Value::Label(stack)]);
+    gen.copy(Value::Label(halt), Value::Local(0));
+    gen.emit(OpCode::JumpIfTrue, &[Value::Const(1), Value::Label(main)]);
+    gen.place(halt);
+    gen.emit(OpCode::Halt, &[]);
+
+    let mut functions = BTreeMap::new();
+
+    for function in program.functions.iter() {
+        functions.insert(gen.code.len(), function.name.clone());
+        gen.function(function)?;
+    }
+
+    if let Some(e) = gen.invalid {
+        return Err(e);
+    }
+
+    let start = gen.code.len();
+    gen.labels[stack] = Some(start + gen.data.len());
+
+    let mut image = gen.code;
+    for (word, fixup) in gen.fixups.iter() {
+        image[*word] = match *fixup {
+            Fixup::Label(label) => gen.labels[label].unwrap() as i64,
+            Fixup::Data(offset) => (start + offset) as i64,
+        };
+    }
+    image.extend(gen.data);
+
+    Ok(Compiled {
+        image,
+        map: SourceMap {
+            lines: gen.lines,
+            end: start,
+            functions,
+            text: text.lines().map(String::from).collect(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Fault, IntCode, Memory};
+
+    fn run(text: &str, input: Vec<i64>) -> Vec<i64> {
+        let compiled = compile(text).unwrap();
+
+        IntCode::new(Memory::new(compiled.image)).try_run(&mut input.clone(), 1_000_000).unwrap()
+    }
+
+    #[test]
+    fn recursion_and_loops() {
+        let text = "
+            fn fib(n) {
+                if (n < 2) { return n; }
+                return fib(n - 1) + fib(n - 2);
+            }
+
+            fn main() {
+                var n = input();
+                while (n > 0) {
+                    output(fib(n));
+                    n = n - 1;
+                }
+            }
+        ";
+
+        assert_eq!(run(text, vec![10]), vec![55, 34, 21, 13, 8, 5, 3, 2, 1, 1]);
+    }
+
+    #[test]
+    fn arrays_and_operators() {
+        let text = "
+            var squares[8], count = 8;
+
+            fn fill(n) {
+                var i = 0;
+                while (i < n) { squares[i] = i * i; i = i + 1; }
+            }
+
+            fn reverse() {
+                var local[8], i = 0;
+                while (i < count) { local[count - 1 - i] = squares[i]; i = i + 1; }
+                i = 0;
+                while (i < count) { output(local[i]); i = i + 1; }
+            }
+
+            fn main() {
+                fill(count);
+                reverse();
+                output(-count * 2 - -1);
+                output(count <= 8 && !(count >= 9) || 0);
+                output(squares[2] != 4);
+            }
+        ";
+
+        assert_eq!(run(text, vec![]), vec![49, 36, 25, 16, 9, 4, 1, 0, -15, 1, 0]);
+    }
+
+    #[test]
+    fn source_map_and_errors() {
+        let text = "fn main() {\n    var x = input();\n    output(x + 1);\n}\n";
+        let compiled = compile(text).unwrap();
+        let out = compiled.image.iter().position(|w| w % 100 == 4).unwrap();
+
+        assert_eq!(compiled.map.describe(out).as_deref(), Some("line 3: output(x + 1);"));
+        assert_eq!(compiled.map.functions.values().collect::<Vec<_>>(), vec!["main"]);
+        assert_eq!(compile("fn main() { y = 1; }").err().as_deref(), Some("line 1: unknown variable `y`"));
+        assert_eq!(compile("fn main() {\n f(1);\n}\nfn f() {}").err().as_deref(), Some("line 2: `f` takes 0 arguments, not 1"));
+        assert_eq!(compile("fn f() {}").err().as_deref(), Some("missing `fn main()`"));
+        assert_eq!(compile("var a[-1];").err().as_deref(), Some("line 1: array size must be between 1 and 1000000"));
+        assert_eq!(compile("fn main() { var a[0]; }").err().as_deref(), Some("line 1: array size must be between 1 and 1000000"));
+        assert!(compile("var a[2];\nfn main() { a[-9223372036854775807] = 1; output(a[9223372036854775807]); }").is_ok());
+
+        for text in ["fn main() { output(-(0 - 9223372036854775807 - 1)); }",
+                     "fn main() { var x = input(); output(x - (0 - 9223372036854775807 - 1)); }"].iter() {
+            let compiled = compile(text).unwrap();
+            let result = IntCode::new(Memory::new(compiled.image)).try_run(&mut vec![1], 1000);
+
+            assert_eq!(result, Err(Fault::Overflow));
+        }
+    }
+}
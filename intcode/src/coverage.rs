@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use super::disasm::{disassemble, word};
+use super::observer::Observer;
+use super::{Instruction, OpCode};
+
+// Which instructions ran and which way each conditional jump went. One
+// observer follows one machine, runs are combined afterwards with `merge`.
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    pub executed: HashMap<usize, u64>,
+    // Per jump address: times taken, times fallen through.
+    pub branches: HashMap<usize, (u64, u64)>,
+    pending: Option<(usize, usize)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Summary {
+    pub instructions: usize,
+    pub executed: usize,
+    pub directions: usize,
+    pub taken: usize,
+}
+
+fn percent(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        100.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+impl Summary {
+    pub fn report(&self) -> String {
+        format!(
+            "instructions: {}/{} ({:.1}%)\nbranch directions: {}/{} ({:.1}%)\n",
+            self.executed, self.instructions, percent(self.executed, self.instructions),
+            self.taken, self.directions, percent(self.taken, self.directions),
+        )
+    }
+}
+
+fn is_branch(op: OpCode) -> bool {
+    matches!(op, OpCode::JumpIfTrue | OpCode::JumpIfFalse)
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    pub fn merge(&mut self, other: &Coverage) {
+        for (addr, count) in other.executed.iter() {
+            *self.executed.entry(*addr).or_insert(0) += count;
+        }
+
+        for (addr, (taken, fallen)) in other.branches.iter() {
+            let branch = self.branches.entry(*addr).or_insert((0, 0));
+
+            branch.0 += taken;
+            branch.1 += fallen;
+        }
+    }
+
+    // Measured against a linear sweep of the original image, so words the sweep
+    // decodes out of data count as instructions and code the program writes at
+    // run time doesn't count at all.
+    pub fn summary(&self, program: &[i64]) -> Summary {
+        let mut summary = Summary { instructions: 0, executed: 0, directions: 0, taken: 0 };
+
+        for (addr, i) in disassemble(program).iter().filter(|(_, i)| i.op != OpCode::Unknown) {
+            summary.instructions += 1;
+            summary.executed += self.executed.contains_key(addr) as usize;
+
+            if is_branch(i.op) {
+                let (taken, fallen) = self.branches.get(addr).cloned().unwrap_or((0, 0));
+
+                summary.directions += 2;
+                summary.taken += (taken > 0) as usize + (fallen > 0) as usize;
+            }
+        }
+
+        summary
+    }
+
+    // The disassembly with execution counts in the margin, `#####` for lines
+    // that never ran, and the directions each conditional jump went.
+    pub fn annotate(&self, program: &[i64]) -> String {
+        disassemble(program).iter()
+            .map(|(addr, i)| {
+                let count = self.executed.get(addr).map_or(String::from("#####"), |c| c.to_string());
+
+                match i.op {
+                    OpCode::Unknown => format!("{:>10}  {:>6}: data {}", "", addr, word(program, *addr)),
+                    op if is_branch(op) => {
+                        let (taken, fallen) = self.branches.get(addr).cloned().unwrap_or((0, 0));
+                        format!("{:>10}  {:>6}: {}  (taken {}, not taken {})", count, addr, i, taken, fallen)
+                    },
+                    _ => format!("{:>10}  {:>6}: {}", count, addr, i),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl Observer for Coverage {
+    fn fetch(&mut self, addr: usize, instruction: &Instruction) {
+        if let Some((site, len)) = self.pending.take() {
+            let branch = self.branches.entry(site).or_insert((0, 0));
+
+            if addr == site + len {
+                branch.1 += 1;
+            } else {
+                branch.0 += 1;
+            }
+        }
+
+        *self.executed.entry(addr).or_insert(0) += 1;
+
+        if is_branch(instruction.op) {
+            self.pending = Some((addr, instruction.len));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{IntCode, Memory};
+
+    #[test]
+    fn merges_runs() {
+        // skips the add unless the input is 0
+        let program = vec![3,3,1105,-1,9,1101,0,0,12,4,12,99,0];
+        let mut total = Coverage::new();
+
+        for input in [0, 5].iter() {
+            let mut intcode = IntCode::new(Memory::new(program.clone()));
+            let coverage = intcode.observe(Coverage::new());
+
+            intcode.run_program(&mut vec![*input]);
+            total.merge(&coverage.borrow());
+        }
+
+        assert_eq!(total.branches[&2], (1, 1));
+        assert_eq!(total.executed[&5], 1);
+        assert_eq!(total.executed[&9], 2);
+        assert_eq!(total.summary(&program), Summary { instructions: 5, executed: 5, directions: 2, taken: 2 });
+    }
+
+    #[test]
+    fn annotated_listing() {
+        let program = vec![3,9,1005,9,8,4,9,99,104,1,99];
+        let mut intcode = IntCode::new(Memory::new(program.clone()));
+        let coverage = intcode.observe(Coverage::new());
+
+        intcode.run_program(&mut vec![0]);
+
+        let coverage = coverage.borrow();
+
+        assert_eq!(coverage.annotate(&program).lines().collect::<Vec<&str>>(), vec![
+            "         1       0: in [9]",
+            "         1       2: jt [9], 8  (taken 0, not taken 1)",
+            "         1       5: out [9]",
+            "         1       7: hlt",
+            "     #####       8: out 1",
+            "     #####      10: hlt",
+        ]);
+        assert_eq!(coverage.summary(&program).report(), "instructions: 4/6 (66.7%)\nbranch directions: 1/2 (50.0%)\n");
+    }
+}
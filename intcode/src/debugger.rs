@@ -0,0 +1,282 @@
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use super::callstack::CallStack;
+use super::compiler::SourceMap;
+use super::journal::Entry;
+use super::{Fault, IntCode, Instruction, OpCode};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Stop {
+    Stepped,
+    Halted,
+    NeedInput,
+    Fault(Fault),
+}
+
+pub struct Debugger {
+    pub cpu: IntCode,
+    pub input: Vec<i64>,
+    pub output: Vec<i64>,
+    pub calls: Rc<RefCell<CallStack>>,
+    // Set when debugging a compiled program, the status then shows the line.
+    pub source: Option<SourceMap>,
+    // The call stack as it was before each step, so stepping back restores it.
+    saved: Vec<CallStack>,
+}
+
+impl Debugger {
+    pub fn new(mut cpu: IntCode, input: Vec<i64>) -> Self {
+        cpu.keep_journal();
+        let calls = cpu.observe(CallStack::new());
+
+        Debugger {
+            cpu,
+            input,
+            output: Vec::new(),
+            calls,
+            source: None,
+            saved: Vec::new(),
+        }
+    }
+
+    pub fn current(&self) -> Instruction {
+        let mut instruction = Instruction::new(self.cpu.mem.peek(self.cpu.ic));
+
+        for i in 0..instruction.len.saturating_sub(1) {
+            instruction.args[i] = Some(self.cpu.mem.peek(self.cpu.ic + i + 1));
+        }
+
+        instruction
+    }
+
+    // A fault stays set on the machine, so stepping again reports it again
+    // until a step back clears it.
+    pub fn step(&mut self) -> Stop {
+        if let Some(fault) = self.cpu.fault.clone() {
+            return Stop::Fault(fault);
+        }
+
+        match self.current().op {
+            OpCode::Halt => return Stop::Halted,
+            OpCode::Input if self.input.is_empty() => return Stop::NeedInput,
+            OpCode::Unknown => {
+                let value = self.cpu.mem.peek(self.cpu.ic);
+                return Stop::Fault(Fault::InvalidOpCode { value, addr: self.cpu.ic });
+            },
+            _ => (),
+        }
+
+        let calls = self.calls.borrow().clone();
+
+        if let Some(i) = self.cpu.next() {
+            self.saved.push(calls);
+            if let Some(v) = self.cpu.execute(i, &mut self.input) {
+                self.output.push(v);
+            }
+        }
+
+        match self.cpu.fault.clone() {
+            Some(fault) => Stop::Fault(fault),
+            None => Stop::Stepped,
+        }
+    }
+
+    pub fn run(&mut self) -> Stop {
+        loop {
+            match self.step() {
+                Stop::Stepped => (),
+                stop => return stop,
+            }
+        }
+    }
+
+    fn undo(&mut self, entries: Vec<Entry>) -> usize {
+        self.cpu.fault = None;
+
+        let keep = self.saved.len().saturating_sub(entries.len());
+
+        if let Some(calls) = self.saved.drain(keep..).next() {
+            *self.calls.borrow_mut() = calls;
+        }
+
+        for entry in entries.iter() {
+            if let Some(v) = entry.input {
+                self.input.insert(0, v);
+            }
+            if entry.output.is_some() {
+                self.output.pop();
+            }
+        }
+
+        entries.len()
+    }
+
+    pub fn back(&mut self) -> bool {
+        let entries = self.cpu.step_back().into_iter().collect();
+
+        self.undo(entries) > 0
+    }
+
+    pub fn back_to_write(&mut self, addr: usize) -> usize {
+        let entries = self.cpu.back_to_write(addr);
+
+        self.undo(entries)
+    }
+
+    pub fn rewind(&mut self, step: usize) -> usize {
+        let entries = self.cpu.rewind(step);
+
+        self.undo(entries)
+    }
+
+    pub fn status(&self) -> String {
+        let status = format!(
+            "step {} ic {} rb {}: {}",
+            self.cpu.steps, self.cpu.ic, self.cpu.relative_base, self.current()
+        );
+
+        match self.source.as_ref().and_then(|s| s.describe(self.cpu.ic)) {
+            Some(line) => format!("{}\n  {}", status, line),
+            None => status,
+        }
+    }
+
+    pub fn command(&mut self, line: &str) -> Option<String> {
+        let parts = line.split_whitespace().collect::<Vec<&str>>();
+        let number = |n: usize| parts.get(n).and_then(|p| p.parse::<i64>().ok());
+        let count = number(1).unwrap_or(1).max(1);
+
+        let reply = match parts.first().cloned().unwrap_or("s") {
+            "s" | "step" => {
+                let stop = (0..count).map(|_| self.step()).find(|s| *s != Stop::Stepped);
+                format!("{:?}", stop.unwrap_or(Stop::Stepped))
+            },
+            "b" | "back" => {
+                let undone = (0..count).take_while(|_| self.back()).count();
+                format!("undid {} steps", undone)
+            },
+            "c" | "continue" => format!("{:?}", self.run()),
+            "w" | "write" => match number(1) {
+                Some(addr) => format!("undid {} steps", self.back_to_write(addr as usize)),
+                None => String::from("usage: w <address>"),
+            },
+            "r" | "rewind" => match number(1) {
+                Some(step) => format!("undid {} steps", self.rewind(step as usize)),
+                None => String::from("usage: r <step>"),
+            },
+            "i" | "input" => {
+                self.input.extend(parts[1..].iter().filter_map(|p| p.parse::<i64>().ok()));
+                format!("input: {:?}", self.input)
+            },
+            "o" | "output" => format!("output: {:?}", self.output),
+            "m" | "mem" => match number(1) {
+                Some(addr) => format!("[{}] = {}", addr, self.cpu.mem.peek(addr as usize)),
+                None => String::from("usage: m <address>"),
+            },
+            "bt" | "backtrace" => {
+                let ic = self.cpu.fault.as_ref().map_or(self.cpu.ic, |f| self.cpu.fault_ic(f));
+                self.calls.borrow().backtrace(ic).to_string()
+            },
+            "q" | "quit" => return None,
+            _ => String::from("commands: s [n], b [n], c, w <addr>, r <step>, i <values>, o, m <addr>, bt, q"),
+        };
+
+        Some(reply)
+    }
+
+    pub fn repl(&mut self) {
+        let stdin = io::stdin();
+
+        println!("{}", self.status());
+        print!("> ");
+        io::stdout().flush().unwrap();
+
+        for line in stdin.lock().lines() {
+            match self.command(&line.unwrap()) {
+                Some(reply) => println!("{}\n{}", reply, self.status()),
+                None => break,
+            }
+
+            print!("> ");
+            io::stdout().flush().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::device::Console;
+    use super::super::Memory;
+
+    fn debugger(buf: Vec<i64>, input: Vec<i64>) -> Debugger {
+        let memory = Memory::new(buf);
+
+        Debugger::new(IntCode::new(memory), input)
+    }
+
+    #[test]
+    fn pauses_for_input() {
+        let mut debugger = debugger(vec![3,9,8,9,10,9,4,9,99,-1,8], vec![]);
+
+        assert_eq!(debugger.run(), Stop::NeedInput);
+
+        debugger.command("i 8");
+
+        assert_eq!(debugger.run(), Stop::Halted);
+        assert_eq!(debugger.output, vec![1]);
+    }
+
+    #[test]
+    fn stepping_back_restores_io() {
+        let mut debugger = debugger(vec![3,9,8,9,10,9,4,9,99,-1,8], vec![8]);
+
+        debugger.run();
+        debugger.command("b 2");
+
+        assert_eq!(debugger.output, vec![]);
+        assert_eq!(debugger.status(), "step 1 ic 2 rb 0: eq [9], [10], [9]");
+
+        debugger.command("r 0");
+
+        assert_eq!(debugger.input, vec![8]);
+        assert_eq!(debugger.cpu.mem.read(9), -1);
+    }
+
+    #[test]
+    fn stops_on_faults() {
+        let mut looping = debugger(vec![1101, 9223372036854775807, 1, 0, 1105, 1, 0], vec![]);
+
+        assert_eq!(looping.run(), Stop::Fault(Fault::Overflow));
+        assert_eq!(looping.step(), Stop::Fault(Fault::Overflow));
+
+        looping.command("b");
+        assert_eq!(looping.status(), "step 0 ic 0 rb 0: add 9223372036854775807, 1, [0]");
+
+        let mut halting = debugger(vec![1101, 9223372036854775807, 1, 0, 99], vec![]);
+        assert_eq!(halting.run(), Stop::Fault(Fault::Overflow));
+
+        let mut unknown = debugger(vec![1101, 1, 1, 0, 42], vec![]);
+        assert_eq!(unknown.run(), Stop::Fault(Fault::InvalidOpCode { value: 42, addr: 4 }));
+    }
+
+    #[test]
+    fn looking_leaves_devices_alone() {
+        // copies one console value to [20]
+        let mut memory = Memory::new(vec![1001,1000,0,20, 99]);
+        let console = memory.map(1000..1001, Console::default()).unwrap();
+        console.borrow_mut().input.extend(vec![5, 6]);
+
+        let mut debugger = Debugger::new(IntCode::new(memory), vec![]);
+
+        debugger.status();
+        debugger.command("m 1000");
+        assert_eq!(console.borrow().input.len(), 2);
+
+        assert_eq!(debugger.run(), Stop::Halted);
+        assert_eq!(console.borrow().input, vec![6]);
+        assert_eq!(debugger.command("m 20").as_deref(), Some("[20] = 5"));
+    }
+}
@@ -0,0 +1,690 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use super::disasm::decode;
+use super::{Instruction, OpCode, ParameterMode};
+
+// Recognizes the usual idioms of compiled Intcode and turns them back into
+// structured pseudo-code:
+//
+// * a call is an immediate store of the return address into a relative cell
+//   followed by an unconditional jump, the callee returns by jumping through
+//   that cell,
+// * relative cells are named after their offset from the base at function
+//   entry, position cells become globals,
+// * `lt`/`eq` into a cell that the next jump tests are folded into its condition,
+// * backward jumps close loops, forward conditional jumps open ifs.
+
+#[derive(Clone, Debug, PartialEq)]
+struct Cond {
+    lhs: String,
+    op: &'static str,
+    rhs: String,
+}
+
+impl Cond {
+    fn negate(&self) -> Cond {
+        let op = match self.op {
+            "<" => ">=",
+            ">=" => "<",
+            "==" => "!=",
+            _ => "==",
+        };
+
+        Cond { lhs: self.lhs.clone(), op, rhs: self.rhs.clone() }
+    }
+
+    fn text(&self) -> String {
+        format!("{} {} {}", self.lhs, self.op, self.rhs)
+    }
+}
+
+struct Branch {
+    first: usize,
+    last: usize,
+    // condition under which the jump is taken
+    cond: Cond,
+    target: Option<usize>,
+    always: bool,
+    never: bool,
+    keep: Option<String>,
+}
+
+struct Function {
+    entry: usize,
+    ret_slot: Option<i64>,
+    body: Vec<(usize, Instruction)>,
+    deltas: HashMap<usize, i64>,
+    reads: HashMap<String, usize>,
+}
+
+struct Line {
+    addr: Option<usize>,
+    depth: usize,
+    text: String,
+}
+
+struct Emit<'f> {
+    f: &'f Function,
+    lines: Vec<Line>,
+    gotos: BTreeSet<usize>,
+}
+
+#[derive(Clone, Copy)]
+struct LoopCtx {
+    head: usize,
+    exit: usize,
+}
+
+pub struct Decompiler<'a> {
+    program: &'a [i64],
+    code: BTreeMap<usize, Instruction>,
+    // jump address -> (callee entry, relative slot holding the return address)
+    calls: HashMap<usize, (usize, i64)>,
+    returns: HashSet<usize>,
+    functions: Vec<Function>,
+}
+
+fn is_jump(i: &Instruction) -> bool {
+    i.op == OpCode::JumpIfTrue || i.op == OpCode::JumpIfFalse
+}
+
+fn is_compare(i: &Instruction) -> bool {
+    i.op == OpCode::LessThan || i.op == OpCode::Equals
+}
+
+fn immediate_cond(i: &Instruction) -> Option<bool> {
+    match i.modes[0] {
+        ParameterMode::Immediate => {
+            let value = i.args[0].unwrap() != 0;
+            Some(if i.op == OpCode::JumpIfTrue { value } else { !value })
+        },
+        _ => None,
+    }
+}
+
+fn target(i: &Instruction) -> Option<usize> {
+    match (&i.modes[1], i.args[1]) {
+        (ParameterMode::Immediate, Some(t)) if t >= 0 => Some(t as usize),
+        _ => None,
+    }
+}
+
+impl<'a> Decompiler<'a> {
+    pub fn new(program: &'a [i64]) -> Self {
+        let mut decompiler = Decompiler {
+            program,
+            code: BTreeMap::new(),
+            calls: HashMap::new(),
+            returns: HashSet::new(),
+            functions: Vec::new(),
+        };
+
+        decompiler.explore();
+        decompiler.split_functions();
+
+        decompiler
+    }
+
+    fn explore(&mut self) {
+        let mut work = vec![0];
+
+        while let Some(addr) = work.pop() {
+            if self.code.contains_key(&addr) || addr >= self.program.len() {
+                continue;
+            }
+
+            let i = decode(self.program, addr);
+            let next = addr + i.len;
+
+            match i.op {
+                OpCode::Unknown => continue,
+                OpCode::Halt => (),
+                OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                    let taken = immediate_cond(&i);
+
+                    if let (Some(t), false) = (target(&i), taken == Some(false)) {
+                        work.push(t);
+                    }
+
+                    match (taken, target(&i)) {
+                        (Some(true), Some(t)) => {
+                            if let Some(slot) = self.call_slot(addr, next) {
+                                self.calls.insert(addr, (t, slot));
+                                work.push(next);
+                            }
+                        },
+                        (Some(true), None) => {
+                            if i.modes[1] == ParameterMode::Relative {
+                                self.returns.insert(addr);
+                            }
+                        },
+                        _ => work.push(next),
+                    }
+                },
+                _ => work.push(next),
+            }
+
+            self.code.insert(addr, i);
+        }
+    }
+
+    // A call stores its own return address into a relative cell right before jumping.
+    fn call_slot(&self, jump: usize, next: usize) -> Option<i64> {
+        if jump < 4 {
+            return None;
+        }
+
+        let store = decode(self.program, jump - 4);
+        let value = match (&store.op, &store.modes) {
+            (OpCode::Add, [ParameterMode::Immediate, ParameterMode::Immediate, ParameterMode::Relative]) => {
+                store.args[0].unwrap().checked_add(store.args[1].unwrap())?
+            },
+            (OpCode::Mul, [ParameterMode::Immediate, ParameterMode::Immediate, ParameterMode::Relative]) => {
+                store.args[0].unwrap().checked_mul(store.args[1].unwrap())?
+            },
+            _ => return None,
+        };
+
+        if value == next as i64 { store.args[2] } else { None }
+    }
+
+    fn split_functions(&mut self) {
+        let mut entries = BTreeMap::<usize, Option<i64>>::new();
+        entries.insert(0, None);
+
+        for (callee, slot) in self.calls.values() {
+            entries.entry(*callee).or_insert(Some(*slot));
+        }
+
+        for (entry, ret_slot) in entries.iter() {
+            let mut deltas = HashMap::<usize, i64>::new();
+            let mut work = vec![(*entry, 0)];
+
+            while let Some((addr, delta)) = work.pop() {
+                if deltas.contains_key(&addr) || (addr != *entry && entries.contains_key(&addr)) {
+                    continue;
+                }
+
+                let i = match self.code.get(&addr) {
+                    Some(i) => i,
+                    None => continue,
+                };
+                let next = addr + i.len;
+
+                deltas.insert(addr, delta);
+
+                match i.op {
+                    OpCode::Halt => (),
+                    OpCode::RelativeBase => {
+                        let step = match i.modes[0] {
+                            ParameterMode::Immediate => i.args[0].unwrap(),
+                            _ => 0,
+                        };
+                        // Past an overflow the frame can't be followed any further.
+                        if let Some(delta) = delta.checked_add(step) {
+                            work.push((next, delta));
+                        }
+                    },
+                    OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                        let taken = immediate_cond(i);
+
+                        if self.calls.contains_key(&addr) {
+                            work.push((next, delta));
+                            continue;
+                        }
+                        if let (Some(t), false) = (target(i), taken == Some(false)) {
+                            work.push((t, delta));
+                        }
+                        if taken != Some(true) {
+                            work.push((next, delta));
+                        }
+                    },
+                    _ => work.push((next, delta)),
+                }
+            }
+
+            let mut body = deltas.keys()
+                .map(|addr| (*addr, decode(self.program, *addr)))
+                .collect::<Vec<(usize, Instruction)>>();
+            body.sort_by_key(|(addr, _)| *addr);
+
+            let mut function = Function {
+                entry: *entry,
+                ret_slot: *ret_slot,
+                body,
+                deltas,
+                reads: HashMap::new(),
+            };
+            function.reads = self.count_reads(&function);
+
+            self.functions.push(function);
+        }
+    }
+
+    fn count_reads(&self, f: &Function) -> HashMap<String, usize> {
+        let mut reads = HashMap::new();
+
+        for (addr, i) in f.body.iter() {
+            let read_args = match i.op {
+                OpCode::Add | OpCode::Mul | OpCode::LessThan | OpCode::Equals => 2,
+                OpCode::JumpIfTrue | OpCode::JumpIfFalse => 2,
+                OpCode::Output | OpCode::RelativeBase => 1,
+                _ => 0,
+            };
+
+            for n in 0..read_args {
+                if i.modes[n] != ParameterMode::Immediate {
+                    *reads.entry(self.value(f, *addr, i, n)).or_insert(0) += 1;
+                }
+            }
+        }
+
+        reads
+    }
+
+    fn slot_name(&self, f: &Function, offset: i64) -> String {
+        match f.ret_slot {
+            Some(r) if r == offset => String::from("ret"),
+            _ if offset < 0 => format!("frame_m{}", offset.unsigned_abs()),
+            _ => format!("frame{}", offset),
+        }
+    }
+
+    fn value(&self, f: &Function, addr: usize, i: &Instruction, n: usize) -> String {
+        let arg = i.args[n].unwrap();
+
+        match i.modes[n] {
+            ParameterMode::Immediate if n < 2 || is_jump(i) => format!("{}", arg),
+            ParameterMode::Relative => match f.deltas[&addr].checked_add(arg) {
+                Some(offset) => self.slot_name(f, offset),
+                None => format!("mem[rb + {}]", arg),
+            },
+            _ => format!("var_{}", arg),
+        }
+    }
+
+    fn index_of(&self, f: &Function, addr: usize) -> Option<usize> {
+        f.body.binary_search_by_key(&addr, |(a, _)| *a).ok()
+    }
+
+    fn branch_at(&self, f: &Function, idx: usize, hi: usize) -> Option<Branch> {
+        let (addr, i) = &f.body[idx];
+
+        if is_compare(i) && idx + 1 < hi {
+            let (jaddr, j) = &f.body[idx + 1];
+            let dst = self.value(f, *addr, i, 2);
+
+            if is_jump(j) && *jaddr == addr + i.len && immediate_cond(j).is_none()
+                && self.value(f, *jaddr, j, 0) == dst {
+                let op = if i.op == OpCode::LessThan { "<" } else { "==" };
+                let compare = Cond { lhs: self.value(f, *addr, i, 0), op, rhs: self.value(f, *addr, i, 1) };
+                let keep = if f.reads.get(&dst).cloned().unwrap_or(0) > 1 {
+                    Some(self.statement(f, idx))
+                } else {
+                    None
+                };
+
+                return Some(Branch {
+                    first: idx,
+                    last: idx + 1,
+                    cond: if j.op == OpCode::JumpIfTrue { compare } else { compare.negate() },
+                    target: target(j),
+                    always: false,
+                    never: false,
+                    keep,
+                });
+            }
+        }
+
+        if is_jump(i) {
+            let test = Cond { lhs: self.value(f, *addr, i, 0), op: "!=", rhs: String::from("0") };
+            let taken = immediate_cond(i);
+
+            return Some(Branch {
+                first: idx,
+                last: idx,
+                cond: if i.op == OpCode::JumpIfTrue { test } else { test.negate() },
+                target: target(i),
+                always: taken == Some(true),
+                never: taken == Some(false),
+                keep: None,
+            });
+        }
+
+        None
+    }
+
+    fn branch_ending_at(&self, f: &Function, last: usize, lo: usize) -> Option<Branch> {
+        if last > lo {
+            if let Some(b) = self.branch_at(f, last - 1, last + 1) {
+                if b.last == last {
+                    return Some(b);
+                }
+            }
+        }
+
+        self.branch_at(f, last, last + 1)
+    }
+
+    // The furthest backward jump in range that lands on `idx` closes a loop.
+    fn loop_tail(&self, f: &Function, idx: usize, hi: usize) -> Option<usize> {
+        let head = f.body[idx].0;
+
+        (idx + 1..hi).rev().find(|j| {
+            let (addr, i) = &f.body[*j];
+            is_jump(i) && target(i) == Some(head) && immediate_cond(i) != Some(false)
+                && !self.calls.contains_key(addr)
+        })
+    }
+
+    fn call_at(&self, f: &Function, idx: usize, hi: usize) -> Option<usize> {
+        if idx + 1 >= hi {
+            return None;
+        }
+
+        let (addr, _) = &f.body[idx];
+        let (jaddr, _) = &f.body[idx + 1];
+
+        match self.calls.get(jaddr) {
+            Some((callee, _)) if *jaddr == addr + 4 => Some(*callee),
+            _ => None,
+        }
+    }
+
+    fn statement(&self, f: &Function, idx: usize) -> String {
+        let (addr, i) = &f.body[idx];
+        let arg = |n| self.value(f, *addr, i, n);
+
+        match i.op {
+            OpCode::Add => format!("{} = {};", arg(2), sum(arg(0), arg(1))),
+            OpCode::Mul => format!("{} = {};", arg(2), product(arg(0), arg(1))),
+            OpCode::LessThan => format!("{} = {} < {};", arg(2), arg(0), arg(1)),
+            OpCode::Equals => format!("{} = {} == {};", arg(2), arg(0), arg(1)),
+            OpCode::Input => format!("{} = input();", arg(0)),
+            OpCode::Output => format!("output({});", arg(0)),
+            OpCode::RelativeBase => match arg(0).strip_prefix('-') {
+                Some(n) => format!("rb -= {};", n),
+                None => format!("rb += {};", arg(0)),
+            },
+            OpCode::Halt => String::from("halt;"),
+            _ => format!("// {}", i),
+        }
+    }
+
+    fn emit(&self, e: &mut Emit, lo: usize, hi: usize, depth: usize, ctx: Option<LoopCtx>) {
+        let f = e.f;
+        let mut idx = lo;
+
+        while idx < hi {
+            let addr = f.body[idx].0;
+
+            if let Some(tail) = self.loop_tail(f, idx, hi) {
+                idx = self.emit_loop(e, idx, tail, depth);
+                continue;
+            }
+
+            if let Some(callee) = self.call_at(f, idx, hi) {
+                e.lines.push(Line { addr: Some(addr), depth, text: format!("{}();", function_name(callee)) });
+                idx += 2;
+                continue;
+            }
+
+            match self.branch_at(f, idx, hi) {
+                Some(b) => idx = self.emit_branch(e, b, hi, depth, ctx),
+                None => {
+                    e.lines.push(Line { addr: Some(addr), depth, text: self.statement(f, idx) });
+                    idx += 1;
+                },
+            }
+        }
+    }
+
+    fn emit_loop(&self, e: &mut Emit, head: usize, tail: usize, depth: usize) -> usize {
+        let f = e.f;
+        let (head_addr, _) = &f.body[head];
+        let (tail_addr, tail_i) = &f.body[tail];
+        let exit = tail_addr + tail_i.len;
+        let ctx = Some(LoopCtx { head: *head_addr, exit });
+        let line = |text: String, depth| Line { addr: Some(*head_addr), depth, text };
+
+        if immediate_cond(tail_i) == Some(true) {
+            match self.branch_at(f, head, tail) {
+                Some(ref b) if b.target == Some(exit) && b.keep.is_none() && !b.always && !b.never => {
+                    e.lines.push(line(format!("while ({}) {{", b.cond.negate().text()), depth));
+                    self.emit(e, b.last + 1, tail, depth + 1, ctx);
+                },
+                _ => {
+                    e.lines.push(line(String::from("loop {"), depth));
+                    self.emit(e, head, tail, depth + 1, ctx);
+                },
+            }
+            e.lines.push(Line { addr: None, depth, text: String::from("}") });
+        } else {
+            let b = self.branch_ending_at(f, tail, head).unwrap();
+
+            e.lines.push(line(String::from("do {"), depth));
+            self.emit(e, head, b.first, depth + 1, ctx);
+            if let Some(keep) = b.keep {
+                e.lines.push(Line { addr: None, depth: depth + 1, text: keep });
+            }
+            e.lines.push(Line { addr: None, depth, text: format!("}} while ({});", b.cond.text()) });
+        }
+
+        tail + 1
+    }
+
+    fn emit_branch(&self, e: &mut Emit, b: Branch, hi: usize, depth: usize, ctx: Option<LoopCtx>) -> usize {
+        let f = e.f;
+        let (addr, jump) = &f.body[b.last];
+        let first_addr = Some(f.body[b.first].0);
+        let next = b.last + 1;
+        let guarded = |text: String| if b.always {
+            text
+        } else {
+            format!("if ({}) {}", b.cond.text(), text)
+        };
+
+        if let Some(keep) = b.keep.clone() {
+            e.lines.push(Line { addr: first_addr, depth, text: keep });
+        }
+
+        if b.never {
+            return next;
+        }
+
+        let text = match (b.target, ctx) {
+            (None, _) if self.returns.contains(addr) || jump.modes[1] == ParameterMode::Relative => {
+                guarded(String::from("return;"))
+            },
+            (None, _) => guarded(format!("goto *{};", self.value(f, *addr, jump, 1))),
+            (Some(t), Some(c)) if t == c.exit => guarded(String::from("break;")),
+            (Some(t), Some(c)) if t == c.head => guarded(String::from("continue;")),
+            (Some(t), _) => {
+                let end = self.index_of(f, t).filter(|end| t > *addr && *end <= hi);
+
+                match end {
+                    Some(end) if !b.always => return self.emit_if(e, &b, end, hi, depth, ctx),
+                    _ => {
+                        e.gotos.insert(t);
+                        guarded(format!("goto L_{};", t))
+                    },
+                }
+            },
+        };
+
+        e.lines.push(Line { addr: first_addr, depth, text });
+
+        next
+    }
+
+    fn emit_if(&self, e: &mut Emit, b: &Branch, end: usize, hi: usize, depth: usize, ctx: Option<LoopCtx>) -> usize {
+        let f = e.f;
+        let first_addr = Some(f.body[b.first].0);
+        let body = b.last + 1;
+        let t = f.body[end].0;
+        let open = Line { addr: first_addr, depth, text: format!("if ({}) {{", b.cond.negate().text()) };
+
+        // An unconditional forward jump closing the body means there is an else part.
+        if end > body + 1 {
+            let (jaddr, j) = &f.body[end - 1];
+            let else_end = target(j)
+                .filter(|t2| *t2 > t && immediate_cond(j) == Some(true) && !self.calls.contains_key(jaddr))
+                .and_then(|t2| self.index_of(f, t2))
+                .filter(|e| *e <= hi);
+
+            if let Some(else_end) = else_end {
+                e.lines.push(open);
+                self.emit(e, body, end - 1, depth + 1, ctx);
+                e.lines.push(Line { addr: None, depth, text: String::from("} else {") });
+                self.emit(e, end, else_end, depth + 1, ctx);
+                e.lines.push(Line { addr: None, depth, text: String::from("}") });
+
+                return else_end;
+            }
+        }
+
+        e.lines.push(open);
+        self.emit(e, body, end, depth + 1, ctx);
+        e.lines.push(Line { addr: None, depth, text: String::from("}") });
+
+        end
+    }
+
+    fn render_function(&self, f: &Function) -> String {
+        let mut e = Emit { f, lines: Vec::new(), gotos: BTreeSet::new() };
+
+        self.emit(&mut e, 0, f.body.len(), 1, None);
+
+        let mut text = format!("fn {}() {{\n", function_name(f.entry));
+        let mut labelled = HashSet::new();
+
+        for line in e.lines.iter() {
+            if let Some(addr) = line.addr {
+                if e.gotos.contains(&addr) && labelled.insert(addr) {
+                    text.push_str(&format!("L_{}:\n", addr));
+                }
+            }
+            text.push_str(&format!("{}{}\n", "    ".repeat(line.depth), line.text));
+        }
+        text.push('}');
+
+        text
+    }
+
+    // Every instruction reachable from the entry point, including the code after calls.
+    pub fn code(&self) -> &BTreeMap<usize, Instruction> {
+        &self.code
+    }
+
+    pub fn render(&self) -> String {
+        let mut functions = self.functions.iter()
+            .map(|f| self.render_function(f))
+            .collect::<Vec<String>>();
+
+        let data = (0..self.program.len())
+            .filter(|addr| !self.covered(*addr))
+            .count();
+        if data > 0 {
+            functions.push(format!("// {} words not reached as code", data));
+        }
+
+        functions.join("\n\n")
+    }
+
+    fn covered(&self, addr: usize) -> bool {
+        match self.code.range(..=addr).next_back() {
+            Some((start, i)) => addr < start + i.len.max(1),
+            None => false,
+        }
+    }
+}
+
+pub fn function_name(entry: usize) -> String {
+    if entry == 0 { String::from("main") } else { format!("func_{}", entry) }
+}
+
+fn sum(a: String, b: String) -> String {
+    match (a.as_str(), b.as_str()) {
+        (_, "0") => a,
+        ("0", _) => b,
+        (_, n) if n.starts_with('-') => format!("{} - {}", a, &n[1..]),
+        _ => format!("{} + {}", a, b),
+    }
+}
+
+fn product(a: String, b: String) -> String {
+    match (a.as_str(), b.as_str()) {
+        ("0", _) | (_, "0") => String::from("0"),
+        (_, "1") => a,
+        ("1", _) => b,
+        (_, "-1") => format!("-{}", a),
+        ("-1", _) => format!("-{}", b),
+        _ => format!("{} * {}", a, b),
+    }
+}
+
+pub fn decompile(program: &[i64]) -> String {
+    Decompiler::new(program).render()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn while_loop() {
+        let program = vec![3,100,1006,100,14,4,100,1001,100,-1,100,1105,1,2,99];
+
+        assert_eq!(decompile(&program), "\
+fn main() {
+    var_100 = input();
+    while (var_100 != 0) {
+        output(var_100);
+        var_100 = var_100 - 1;
+    }
+    halt;
+}");
+    }
+
+    #[test]
+    fn if_else() {
+        let program = vec![3,100,1007,100,5,101,1005,101,14,104,1,1105,1,16,104,0,99];
+
+        assert_eq!(decompile(&program), "\
+fn main() {
+    var_100 = input();
+    if (var_100 >= 5) {
+        output(1);
+    } else {
+        output(0);
+    }
+    halt;
+}");
+    }
+
+    #[test]
+    fn survives_overflowing_constants() {
+        let max = i64::MAX;
+        let program = vec![21101,max,1,0, 1105,1,8, 99, 109,max, 109,max, 204,max, 22201,-1,max,0, 99];
+
+        // the frame is lost after the second adjustment, so the walk stops there
+        assert!(decompile(&program).contains("    rb += 9223372036854775807;\n    rb += 9223372036854775807;\n}"));
+    }
+
+    #[test]
+    fn call_and_return() {
+        let program = vec![109,50,21101,9,0,0,1105,1,10,99,104,42,2106,0,0];
+
+        assert_eq!(decompile(&program), "\
+fn main() {
+    rb += 50;
+    func_10();
+    halt;
+}
+
+fn func_10() {
+    output(42);
+    return;
+}");
+    }
+}
@@ -0,0 +1,204 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+use std::time::Instant;
+
+use super::fuzz::Rng;
+use super::screen::{Palette, Screen};
+use super::Memory;
+
+// A host peripheral living at a range of addresses. Offsets are relative to the
+// start of the range it is mapped at.
+pub trait Device {
+    fn read(&mut self, offset: usize) -> i64;
+    fn write(&mut self, offset: usize, value: i64);
+
+    // How many addresses the device answers to, None when any offset will do.
+    fn size(&self) -> Option<usize> {
+        None
+    }
+}
+
+type Shared = Rc<RefCell<dyn Device>>;
+
+// Devices mapped into a memory. Shared so the host keeps a handle to each one
+// and a cloned memory talks to the same peripherals.
+#[derive(Clone, Default)]
+pub struct Devices(Vec<(Range<usize>, Shared)>);
+
+impl fmt::Debug for Devices {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.0.iter().map(|(range, _)| range)).finish()
+    }
+}
+
+impl Devices {
+    pub fn find(&self, addr: usize) -> Option<(&Shared, usize)> {
+        self.0.iter()
+            .find(|(range, _)| range.contains(&addr))
+            .map(|(range, device)| (device, addr - range.start))
+    }
+}
+
+impl Memory {
+    // Maps a device over `range`, reads and writes there no longer reach the
+    // backing store. Returns a handle to the device, or None if the range
+    // overlaps one already mapped or is larger than the device.
+    pub fn map<D: Device + 'static>(&mut self, range: Range<usize>, device: D) -> Option<Rc<RefCell<D>>> {
+        let overlaps = self.devices.0.iter().any(|(r, _)| r.start < range.end && range.start < r.end);
+        let oversized = device.size().is_some_and(|size| range.len() > size);
+
+        if overlaps || oversized || range.is_empty() {
+            return None;
+        }
+
+        let shared = Rc::new(RefCell::new(device));
+
+        self.devices.0.push((range, shared.clone()));
+        Some(shared)
+    }
+}
+
+// Milliseconds since the clock was mapped, writes are ignored.
+pub struct Clock(Instant);
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock(Instant::now())
+    }
+}
+
+impl Device for Clock {
+    fn read(&mut self, _offset: usize) -> i64 {
+        self.0.elapsed().as_millis() as i64
+    }
+
+    fn write(&mut self, _offset: usize, _value: i64) {}
+}
+
+// A fresh non-negative number on every read, writing reseeds it.
+pub struct Random(Rng);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random(Rng::new(seed))
+    }
+}
+
+impl Device for Random {
+    fn read(&mut self, _offset: usize) -> i64 {
+        (self.0.next_u64() >> 1) as i64
+    }
+
+    fn write(&mut self, _offset: usize, value: i64) {
+        self.0 = Rng::new(value as u64);
+    }
+}
+
+// `width` by `height` cells laid out row by row.
+pub struct Framebuffer {
+    width: usize,
+    pub cells: Vec<i64>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Result<Self, String> {
+        if width == 0 {
+            return Err(String::from("framebuffer width must be at least 1"));
+        }
+
+        Ok(Framebuffer {
+            width,
+            cells: vec![0; width * height],
+        })
+    }
+
+    pub fn screen(&self, palette: Palette) -> Screen {
+        let mut screen = Screen::new(palette);
+
+        screen.pixels(self.width, &self.cells);
+        screen
+    }
+}
+
+impl Device for Framebuffer {
+    fn read(&mut self, offset: usize) -> i64 {
+        self.cells[offset]
+    }
+
+    fn write(&mut self, offset: usize, value: i64) {
+        self.cells[offset] = value;
+    }
+
+    fn size(&self) -> Option<usize> {
+        Some(self.cells.len())
+    }
+}
+
+// Reads take the next queued value, or -1 when nothing is waiting, writes are
+// collected as output.
+#[derive(Default)]
+pub struct Console {
+    pub input: VecDeque<i64>,
+    pub output: Vec<i64>,
+}
+
+impl Console {
+    pub fn text(&self) -> String {
+        self.output.iter().filter_map(|c| std::char::from_u32(*c as u32)).collect()
+    }
+}
+
+impl Device for Console {
+    fn read(&mut self, _offset: usize) -> i64 {
+        self.input.pop_front().unwrap_or(-1)
+    }
+
+    fn write(&mut self, _offset: usize, value: i64) {
+        self.output.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::IntCode;
+
+    #[test]
+    fn console_and_framebuffer() {
+        // copies the console into the framebuffer until it reads -1, then writes "ok"
+        let program = vec![
+            1001,1000,0,100, 1008,100,-1,101, 1005,101,22, 1001,100,0,2000, 1001,14,1,14, 1105,1,0,
+            1101,0,111,1000, 1101,0,107,1000, 99,
+        ];
+        let mut memory = Memory::new(program);
+        let console = memory.map(1000..1001, Console::default()).unwrap();
+        let screen = memory.map(2000..2004, Framebuffer::new(2, 2).unwrap()).unwrap();
+
+        console.borrow_mut().input.extend(vec![1, 0, 0, 1]);
+        assert!(memory.map(2003..2010, Clock::default()).is_none());
+        assert!(memory.map(0..100, Framebuffer::new(2, 2).unwrap()).is_none());
+
+        IntCode::new(memory).run_program(&mut vec![]);
+
+        assert_eq!(console.borrow().text(), "ok");
+        assert_eq!(screen.borrow().cells, vec![1, 0, 0, 1]);
+        assert_eq!(screen.borrow().screen(Palette::hull()).render(), "# \n #");
+        assert_eq!(Framebuffer::new(0, 6).err().as_deref(), Some("framebuffer width must be at least 1"));
+    }
+
+    #[test]
+    fn seeded_random() {
+        let mut a = Memory::new(vec![]);
+        let mut b = Memory::new(vec![]);
+
+        a.map(10..11, Random::new(7));
+        b.map(10..11, Random::new(7));
+
+        assert_eq!((a.read(10), a.read(10)), (b.read(10), b.read(10)));
+        assert!(a.read(10) >= 0);
+        assert_eq!(a.read(11), 0);
+    }
+}
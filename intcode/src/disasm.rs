@@ -0,0 +1,110 @@
+use std::fmt;
+
+use super::{Instruction, OpCode, ParameterMode};
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mnemonic = match self {
+            OpCode::Add => "add",
+            OpCode::Mul => "mul",
+            OpCode::Halt => "hlt",
+            OpCode::Input => "in",
+            OpCode::Output => "out",
+            OpCode::JumpIfTrue => "jt",
+            OpCode::JumpIfFalse => "jf",
+            OpCode::LessThan => "lt",
+            OpCode::Equals => "eq",
+            OpCode::RelativeBase => "arb",
+            OpCode::Unknown => "???",
+        };
+
+        write!(f, "{}", mnemonic)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.op)?;
+
+        let operands = self.args.iter()
+            .zip(self.modes.iter())
+            .filter_map(|(arg, mode)| arg.map(|a| operand(a, mode)))
+            .collect::<Vec<String>>();
+
+        if !operands.is_empty() {
+            write!(f, " {}", operands.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn operand(arg: i64, mode: &ParameterMode) -> String {
+    match mode {
+        ParameterMode::Immediate => format!("{}", arg),
+        ParameterMode::Position => format!("[{}]", arg),
+        ParameterMode::Relative if arg < 0 => format!("[rb-{}]", -arg),
+        ParameterMode::Relative => format!("[rb+{}]", arg),
+    }
+}
+
+pub fn word(program: &[i64], addr: usize) -> i64 {
+    program.get(addr).cloned().unwrap_or(0)
+}
+
+// Decodes the instruction at `addr` with its arguments filled in, the same way
+// `IntCode::next` does but straight from a program image.
+pub fn decode(program: &[i64], addr: usize) -> Instruction {
+    let mut instruction = Instruction::new(word(program, addr));
+
+    for i in 0..instruction.len.saturating_sub(1) {
+        instruction.args[i] = Some(word(program, addr + i + 1));
+    }
+
+    instruction
+}
+
+// Linear sweep over the whole image, words that don't decode are reported as data.
+pub fn disassemble(program: &[i64]) -> Vec<(usize, Instruction)> {
+    let mut listing = Vec::new();
+    let mut addr = 0;
+
+    while addr < program.len() {
+        let instruction = decode(program, addr);
+        let len = instruction.len.max(1);
+
+        listing.push((addr, instruction));
+        addr += len;
+    }
+
+    listing
+}
+
+pub fn listing(program: &[i64]) -> String {
+    disassemble(program).iter()
+        .map(|(addr, i)| match i.op {
+            OpCode::Unknown => format!("{:>6}: data {}", addr, word(program, *addr)),
+            _ => format!("{:>6}: {}", addr, i),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_modes() {
+        let program = vec![21101, 9, 0, -3];
+
+        assert_eq!(format!("{}", decode(&program, 0)), "add 9, 0, [rb-3]");
+    }
+
+    #[test]
+    fn listing_with_data() {
+        let program = vec![1002, 4, 3, 4, 33, 99];
+
+        assert_eq!(listing(&program), "     0: mul [4], 3, [4]\n     4: data 33\n     5: hlt");
+    }
+}
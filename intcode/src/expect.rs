@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{Fault, IntCode, Memory};
+
+// A walkthrough for a program that talks in ASCII, written as:
+//
+//     timeout 50000
+//     expect Command?
+//     send north
+//     expect Command?
+//     goto blocked if You can't go that way
+//     send take lamp
+//     halt
+//     label blocked
+//     fail could not go north
+//
+// `expect` runs the program until its output contains the text and `halt`
+// until it stops. `goto <label> if <text>` jumps when the text was part of the
+// output the last `expect` or `halt` consumed, a bare `goto` always jumps.
+// `send` types a line, `timeout` is the number of steps each wait may take and
+// `fail` stops the script. Lines starting with `#` are comments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Step {
+    Expect(String),
+    Halt,
+    Send(String),
+    Goto { target: usize, pattern: Option<String> },
+    Timeout(usize),
+    Fail(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Script {
+    // Each step with the line it came from.
+    steps: Vec<(usize, Step)>,
+}
+
+const DEFAULT_TIMEOUT: usize = 1_000_000;
+
+// Guards against a script looping on its own without the program running.
+const MAX_JUMPS: usize = 100_000;
+
+pub fn parse(text: &str) -> Result<Script, String> {
+    let mut steps = Vec::new();
+    let mut labels = HashMap::<String, usize>::new();
+    let mut gotos = Vec::<(usize, String, Option<String>)>::new();
+
+    for (n, line) in text.lines().enumerate().map(|(n, l)| (n + 1, l.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, rest) = match line.find(char::is_whitespace) {
+            Some(at) => (&line[..at], line[at..].trim()),
+            None => (line, ""),
+        };
+        let text = || match rest {
+            "" => Err(format!("line {}: {} needs some text", n, key)),
+            _ => Ok(rest.to_string()),
+        };
+
+        match key {
+            "expect" => steps.push((n, Step::Expect(text()?))),
+            "halt" => steps.push((n, Step::Halt)),
+            "send" => steps.push((n, Step::Send(rest.to_string()))),
+            "fail" => steps.push((n, Step::Fail(rest.to_string()))),
+            "timeout" => {
+                let steps_allowed = rest.parse().map_err(|_| format!("line {}: bad timeout {}", n, rest))?;
+                steps.push((n, Step::Timeout(steps_allowed)));
+            },
+            "label" => {
+                if labels.insert(text()?, steps.len()).is_some() {
+                    return Err(format!("line {}: label {} is already defined", n, rest));
+                }
+            },
+            "goto" => {
+                let (label, condition) = match rest.split_once(char::is_whitespace) {
+                    Some((label, condition)) => (label, Some(condition.trim())),
+                    None => (rest, None),
+                };
+                let pattern = match condition.map(|c| c.strip_prefix("if").filter(|p| p.starts_with(char::is_whitespace))) {
+                    Some(Some(pattern)) => Some(pattern.trim().to_string()),
+                    Some(None) => return Err(format!("line {}: expected `if` after goto {}", n, label)),
+                    None => None,
+                };
+
+                if label.is_empty() {
+                    return Err(format!("line {}: goto needs a label", n));
+                }
+                gotos.push((steps.len(), label.to_string(), pattern.clone()));
+                steps.push((n, Step::Goto { target: 0, pattern }));
+            },
+            _ => return Err(format!("line {}: unknown key {}", n, key)),
+        }
+    }
+
+    for (at, label, pattern) in gotos {
+        let target = *labels.get(&label)
+            .ok_or_else(|| format!("line {}: undefined label {}", steps[at].0, label))?;
+
+        steps[at].1 = Step::Goto { target, pattern };
+    }
+
+    Ok(Script { steps })
+}
+
+pub fn load(path: &Path) -> io::Result<Script> {
+    parse(&fs::read_to_string(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+}
+
+// What a script saw. `transcript` holds the program's text with the lines it
+// read, outputs that aren't ASCII (usually the answer) are kept in `values`.
+#[derive(Debug)]
+pub struct Run {
+    pub transcript: String,
+    pub values: Vec<i64>,
+    pub steps: usize,
+    pub result: Result<(), String>,
+}
+
+struct Session {
+    cpu: IntCode,
+    input: Vec<i64>,
+    // Output not yet consumed by a wait.
+    pending: String,
+    transcript: String,
+    values: Vec<i64>,
+}
+
+impl Session {
+    // Runs until `pattern` shows up in the output, or until the program halts
+    // when there is none, and returns the output up to the end of the match.
+    fn wait(&mut self, pattern: Option<&str>, timeout: usize) -> Result<String, String> {
+        let limit = self.cpu.steps.saturating_add(timeout);
+        let waiting = || pattern.map_or(String::from("the program to halt"), |p| format!("\"{}\"", p));
+        let mut output = Vec::new();
+
+        loop {
+            if let Some(end) = pattern.and_then(|p| self.pending.find(p).map(|at| at + p.len())) {
+                return Ok(self.pending.drain(..end).collect());
+            }
+
+            let next = self.input.first().copied();
+            let unread = self.input.len();
+            let running = match self.cpu.try_step(&mut self.input, &mut output, limit) {
+                Ok(running) => running,
+                Err(Fault::NeedInput { .. }) => return Err(format!("program wants input while waiting for {}", waiting())),
+                Err(Fault::StepLimit { .. }) => return Err(format!("timed out after {} steps waiting for {}", timeout, waiting())),
+                Err(fault) => return Err(fault.to_string()),
+            };
+
+            // Sent lines are echoed as the program reads them, so they land
+            // in the transcript after the prompt.
+            if let Some(c) = next.filter(|_| self.input.len() < unread) {
+                self.transcript.push(c as u8 as char);
+            }
+
+            for value in output.drain(..) {
+                match value {
+                    0..=127 => {
+                        self.pending.push(value as u8 as char);
+                        self.transcript.push(value as u8 as char);
+                    },
+                    _ => self.values.push(value),
+                }
+            }
+
+            if !running {
+                return match pattern {
+                    Some(_) => Err(format!("program halted while waiting for {}", waiting())),
+                    None => Ok(self.pending.drain(..).collect()),
+                };
+            }
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        self.input.extend(line.bytes().map(i64::from));
+        self.input.push(10);
+    }
+}
+
+impl Script {
+    pub fn run(&self, program: Vec<i64>) -> Run {
+        let mut session = Session {
+            cpu: IntCode::new(Memory::new(program)),
+            input: Vec::new(),
+            pending: String::new(),
+            transcript: String::new(),
+            values: Vec::new(),
+        };
+        let result = self.drive(&mut session);
+
+        Run {
+            transcript: session.transcript,
+            values: session.values,
+            steps: session.cpu.steps,
+            result,
+        }
+    }
+
+    fn drive(&self, session: &mut Session) -> Result<(), String> {
+        let mut timeout = DEFAULT_TIMEOUT;
+        let mut consumed = String::new();
+        let mut jumps = 0;
+        let mut at = 0;
+
+        while let Some((n, step)) = self.steps.get(at) {
+            let fail = |reason: String| format!("line {}: {}", n, reason);
+
+            at += 1;
+
+            match step {
+                Step::Expect(pattern) => consumed = session.wait(Some(pattern), timeout).map_err(fail)?,
+                Step::Halt => consumed = session.wait(None, timeout).map_err(fail)?,
+                Step::Send(line) => session.send(line),
+                Step::Timeout(steps) => timeout = *steps,
+                Step::Fail(reason) => return Err(fail(reason.clone())),
+                Step::Goto { target, pattern } => {
+                    if pattern.as_ref().is_none_or(|p| consumed.contains(p.as_str())) {
+                        jumps += 1;
+                        if jumps > MAX_JUMPS {
+                            return Err(fail(format!("more than {} jumps", MAX_JUMPS)));
+                        }
+                        at = *target;
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::compiler::compile;
+
+    fn say(text: &str) -> String {
+        text.bytes().map(|b| format!("output({});", b)).collect()
+    }
+
+    // Asks for a password until it gets one starting with `o`, then prints the
+    // number of attempts.
+    fn door() -> Vec<i64> {
+        let text = format!("
+            fn main() {{
+                var attempts = 0, open = 0;
+                while (!open) {{
+                    {}
+                    var c = input(), first = c;
+                    while (c != 10) {{ c = input(); }}
+                    attempts = attempts + 1;
+                    if (first == 111) {{ {} open = 1; }} else {{ {} }}
+                }}
+                output(attempts + 1000);
+            }}",
+            say("Password? "), say("Welcome.\n"), say("Wrong.\n"));
+
+        compile(&text).unwrap().image
+    }
+
+    #[test]
+    fn branches_on_output() {
+        let script = parse("
+            # Tries a wrong word first.
+            expect Password?
+            send swordfish
+            expect .
+            goto retry if Wrong
+            fail the first try worked
+            label retry
+            expect Password?
+            send open sesame
+            halt
+            goto done if Welcome
+            fail never got in
+            label done
+        ").unwrap();
+        let run = script.run(door());
+
+        assert_eq!(run.result, Ok(()));
+        assert_eq!(run.values, vec![1002]);
+        assert_eq!(run.transcript, "Password? swordfish\nWrong.\nPassword? open sesame\nWelcome.\n");
+    }
+
+    #[test]
+    fn failures_and_errors() {
+        let run = parse("expect Password?\nexpect Welcome").unwrap().run(door());
+        assert_eq!(run.result.err().as_deref(), Some("line 2: program wants input while waiting for \"Welcome\""));
+
+        let run = parse("timeout 5\nexpect Nope").unwrap().run(door());
+        assert_eq!(run.result.err().as_deref(), Some("line 2: timed out after 5 steps waiting for \"Nope\""));
+
+        let run = parse("send open\nhalt\nexpect More").unwrap().run(door());
+        assert_eq!(run.result.err().as_deref(), Some("line 3: program halted while waiting for \"More\""));
+
+        let run = parse("label loop\ngoto loop").unwrap().run(door());
+        assert_eq!(run.result.err().as_deref(), Some("line 2: more than 100000 jumps"));
+
+        assert_eq!(parse("goto nowhere").err().as_deref(), Some("line 1: undefined label nowhere"));
+        assert_eq!(parse("goto x when y\nlabel x").err().as_deref(), Some("line 1: expected `if` after goto x"));
+        assert_eq!(parse("expect").err().as_deref(), Some("line 1: expect needs some text"));
+    }
+
+    #[test]
+    fn checked_in_walkthrough() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scripts");
+        let program = fs::read_to_string(dir.join("door")).unwrap()
+            .trim()
+            .split(',')
+            .map(|v| v.parse::<i64>().unwrap())
+            .collect();
+        let run = load(&dir.join("door.exp")).unwrap().run(program);
+
+        assert_eq!(run.result, Ok(()));
+        assert_eq!(run.values, vec![1003]);
+        assert!(run.transcript.ends_with("north\nYou step outside.\n"));
+    }
+}
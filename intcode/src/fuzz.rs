@@ -0,0 +1,184 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use super::{Fault, Instruction, IntCode, Memory, OpCode, ParameterMode};
+
+const OPCODES: [i64; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 99];
+const EXTREMES: [i64; 6] = [i64::MIN, i64::MIN + 1, -1, i64::MAX, 1 << 40, -(1 << 40)];
+
+// xorshift64*, plenty for generating programs and keeps runs reproducible from a seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    // Mostly opcodes and small numbers so programs get past their first few
+    // instructions, with the odd extreme value to shake out overflows.
+    pub fn cell(&mut self) -> i64 {
+        match self.below(10) {
+            0..=3 => {
+                let op = OPCODES[self.below(OPCODES.len() as u64) as usize];
+                let modes = [self.below(3), self.below(3), self.below(3)];
+
+                Instruction {
+                    op: OpCode::from(op),
+                    modes: [
+                        ParameterMode::from(modes[0] as i64),
+                        ParameterMode::from(modes[1] as i64),
+                        ParameterMode::from(modes[2] as i64),
+                    ],
+                    ..Default::default()
+                }.label()
+            },
+            4..=7 => self.below(64) as i64 - 8,
+            8 => EXTREMES[self.below(EXTREMES.len() as u64) as usize],
+            _ => self.next_u64() as i64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub steps: usize,
+    pub memory: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            steps: 10_000,
+            memory: 1 << 16,
+        }
+    }
+}
+
+pub fn program(rng: &mut Rng, len: usize) -> Vec<i64> {
+    (0..len).map(|_| rng.cell()).collect()
+}
+
+// Changes, inserts or removes a few cells of an existing program.
+pub fn mutate(rng: &mut Rng, program: &[i64]) -> Vec<i64> {
+    let mut mutant = program.to_vec();
+
+    for _ in 0..=rng.below(4) {
+        let at = rng.below(mutant.len() as u64 + 1) as usize;
+
+        match rng.below(3) {
+            0 if at < mutant.len() => mutant[at] = rng.cell(),
+            1 => mutant.insert(at, rng.cell()),
+            _ if at < mutant.len() => {
+                mutant.remove(at);
+            },
+            _ => (),
+        }
+    }
+
+    mutant
+}
+
+// Runs one program and checks it either finished or faulted without panicking
+// and without growing memory past the limit.
+pub fn check(program: &[i64], input: &[i64], limits: Limits) -> Result<Result<Vec<i64>, Fault>, String> {
+    let memory = Memory::new(program.to_vec());
+    let mut intcode = IntCode::new(memory);
+    let mut input = input.to_vec();
+
+    intcode.memory_limit = Some(limits.memory);
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| intcode.try_run(&mut input, limits.steps)))
+        .map_err(|e| {
+            e.downcast_ref::<String>().cloned()
+                .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| String::from("panic"))
+        })?;
+    let size = intcode.mem.bucket.borrow().len();
+
+    if size > limits.memory.max(program.len()) {
+        return Err(format!("memory grew to {} cells", size));
+    }
+
+    Ok(result)
+}
+
+#[derive(Debug)]
+pub struct Failure {
+    pub program: Vec<i64>,
+    pub input: Vec<i64>,
+    pub reason: String,
+}
+
+// Runs `iterations` programs, each either random or a mutation of one of the
+// seeds, and returns the first one that broke the VM.
+pub fn fuzz(rng: &mut Rng, seeds: &[Vec<i64>], iterations: usize, limits: Limits) -> Option<Failure> {
+    for _ in 0..iterations {
+        let program = match rng.below(seeds.len() as u64 + 1) as usize {
+            0 => {
+                let len = 1 + rng.below(64) as usize;
+                program(rng, len)
+            },
+            n => mutate(rng, &seeds[n - 1]),
+        };
+        let input = (0..rng.below(8)).map(|_| rng.cell()).collect::<Vec<i64>>();
+
+        if let Err(reason) = check(&program, &input, limits) {
+            return Some(Failure { program, input, reason });
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_programs_never_panic() {
+        let seeds = vec![
+            vec![3,9,8,9,10,9,4,9,99,-1,8],
+            vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99],
+        ];
+        let failure = fuzz(&mut Rng::new(2019), &seeds, 5_000, Limits::default());
+
+        assert!(failure.is_none(), "{:?}", failure);
+    }
+
+    #[test]
+    fn faults_instead_of_panics() {
+        let limits = Limits { steps: 100, memory: 64 };
+
+        assert_eq!(check(&[1, -1, 0, 0, 99], &[], limits), Ok(Err(Fault::BadAddress { addr: -1 })));
+        assert_eq!(check(&[1101, 1, 1, 1000, 99], &[], limits), Ok(Err(Fault::MemoryLimit { addr: 1000 })));
+        assert_eq!(check(&[1102, i64::MAX, 2, 0, 99], &[], limits), Ok(Err(Fault::Overflow)));
+        assert_eq!(check(&[1106, 0, -5], &[], limits), Ok(Err(Fault::BadAddress { addr: -5 })));
+        assert_eq!(check(&[109, -3, 204, 4, 99], &[], limits), Ok(Ok(vec![-3])));
+    }
+
+    #[test]
+    fn labels_round_trip() {
+        let modes = [ParameterMode::Position, ParameterMode::Immediate, ParameterMode::Relative];
+
+        for op in OPCODES.iter() {
+            for m in 0..27 {
+                let label = (m / 9 * 10_000) + (m / 3 % 3 * 1_000) + (m % 3 * 100) + op;
+                let instruction = Instruction::new(label);
+
+                assert_ne!(instruction.op, OpCode::Unknown);
+                assert_eq!(instruction.modes[0], modes[(m % 3) as usize]);
+                assert_eq!(instruction.label(), label);
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::profile::Profile;
+use super::Instruction;
+
+// Renders memory as rows of `width` cells with an ASCII column, hexdump style.
+// With a profile attached every cell is followed by a mark: `*` for cells of an
+// executed instruction, `+` written, `-` only read.
+pub struct Inspector<'a> {
+    cells: &'a [i64],
+    marks: HashMap<usize, char>,
+    pub width: usize,
+}
+
+impl<'a> Inspector<'a> {
+    pub fn new(cells: &'a [i64]) -> Self {
+        Inspector {
+            cells,
+            marks: HashMap::new(),
+            width: 8,
+        }
+    }
+
+    pub fn profile(mut self, profile: &Profile) -> Self {
+        for addr in profile.reads.keys() {
+            self.marks.insert(*addr, '-');
+        }
+        for addr in profile.writes.keys() {
+            self.marks.insert(*addr, '+');
+        }
+        for addr in profile.executed.keys() {
+            let len = Instruction::new(self.read(*addr)).len.max(1);
+
+            for a in *addr..*addr + len {
+                self.marks.insert(a, '*');
+            }
+        }
+
+        self
+    }
+
+    pub fn read(&self, addr: usize) -> i64 {
+        self.cells.get(addr).cloned().unwrap_or(0)
+    }
+
+    pub fn mark(&self, addr: usize) -> char {
+        self.marks.get(&addr).cloned().unwrap_or(' ')
+    }
+
+    pub fn dump(&self, range: Range<usize>) -> String {
+        let cell_width = range.clone().map(|a| self.read(a).to_string().len()).max().unwrap_or(1);
+        let addr_width = range.end.saturating_sub(1).to_string().len().max(4);
+        let mut out = String::new();
+
+        for row in range.clone().step_by(self.width.max(1)) {
+            let addrs = row..(row + self.width).min(range.end);
+
+            out.push_str(&format!("{:>w$}:", row, w = addr_width));
+            for a in addrs.clone() {
+                out.push_str(&format!(" {:>w$}", self.read(a), w = cell_width));
+                if !self.marks.is_empty() {
+                    out.push(self.mark(a));
+                }
+            }
+            out.push_str(&format!("  |{}|\n", addrs.map(|a| ascii(self.read(a))).collect::<String>()));
+        }
+
+        out
+    }
+}
+
+pub fn ascii(value: i64) -> char {
+    match value {
+        32..=126 => value as u8 as char,
+        _ => '.',
+    }
+}
+
+// Cells whose value differs between two snapshots, cells past the end of the
+// shorter one count as zero.
+pub fn diff(before: &[i64], after: &[i64]) -> Vec<(usize, i64, i64)> {
+    let at = |cells: &[i64], a: usize| cells.get(a).cloned().unwrap_or(0);
+
+    (0..before.len().max(after.len()))
+        .filter(|a| at(before, *a) != at(after, *a))
+        .map(|a| (a, at(before, a), at(after, a)))
+        .collect()
+}
+
+pub fn render_diff(changes: &[(usize, i64, i64)]) -> String {
+    changes.iter()
+        .map(|(addr, old, new)| format!("{:>6}: {} -> {}\n", addr, old, new))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{IntCode, Memory};
+
+    #[test]
+    fn dump_with_annotations() {
+        let program = vec![1, 9, 10, 11, 104, 72, 99, 0, 0, 30, 40, 0];
+        let memory = Memory::new(program.clone());
+        let mut intcode = IntCode::new(memory);
+
+        let profile = intcode.observe(Profile::new());
+        intcode.run_program(&mut vec![]);
+
+        let after = intcode.mem.snapshot();
+        let mut inspector = Inspector::new(&after).profile(&profile.borrow());
+
+        inspector.width = 6;
+
+        assert_eq!(inspector.dump(0..12), concat!(
+            "   0:   1*   9*  10*  11* 104*  72*  |....hH|\n",
+            "   6:  99*   0    0   30-  40-  70+  |c...(F|\n",
+        ));
+        assert_eq!(Inspector::new(&after).dump(4..6), "   4: 104  72  |hH|\n");
+        assert_eq!(diff(&program, &after), vec![(11, 0, 70)]);
+        assert_eq!(render_diff(&diff(&after, &program)), "    11: 70 -> 0\n");
+    }
+}
@@ -0,0 +1,519 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, InstBuilder, MemFlags, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use super::observer::Observer;
+use super::{Fault, IntCode, Instruction, Memory, OpCode, ParameterMode};
+
+// Compiles hot straight-line runs of Intcode to native code with Cranelift.
+//
+// The interpreter counts how often it arrives at each address, past
+// `threshold` the block starting there is compiled: arithmetic, comparisons
+// and relative base changes up to and including the first jump. Anything the
+// native code can't do exactly like the interpreter (I/O, a read past the end
+// of memory, overflow, a bad address, a write into compiled code) leaves the
+// block before that instruction and the interpreter carries on from there.
+// Writes the interpreter makes into compiled code throw the blocks covering
+// them away, they get compiled again if they stay hot.
+
+// Shared with the generated code, the offsets below are baked into it.
+#[repr(C)]
+struct Context {
+    mem: *mut i64,
+    len: i64,
+    code: *const u8,
+    code_len: i64,
+    relative_base: i64,
+    steps: i64,
+}
+
+const MEM: i32 = 0;
+const LEN: i32 = 8;
+const CODE: i32 = 16;
+const CODE_LEN: i32 = 24;
+const RELATIVE_BASE: i32 = 32;
+const STEPS: i32 = 40;
+
+// The longest block we compile, in instructions.
+const MAX_BLOCK: usize = 64;
+
+type Native = unsafe extern "C" fn(*mut Context) -> i64;
+
+struct Compiled {
+    native: Native,
+    start: usize,
+    end: usize,
+    len: usize,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub compiled: usize,
+    pub deoptimized: usize,
+    pub native_steps: usize,
+}
+
+// Addresses the interpreter wrote to since the last look.
+#[derive(Default)]
+struct Dirty(Vec<usize>);
+
+impl Observer for Dirty {
+    fn write(&mut self, addr: usize, _old: i64, _new: i64) {
+        self.0.push(addr);
+    }
+}
+
+pub struct Jit {
+    cpu: IntCode,
+    module: JITModule,
+    blocks: HashMap<usize, Compiled>,
+    counts: HashMap<usize, usize>,
+    rejected: HashSet<usize>,
+    // Non-zero for every word some compiled block was decoded from.
+    code: Vec<u8>,
+    dirty: Rc<RefCell<Dirty>>,
+    threshold: usize,
+    pub stats: Stats,
+}
+
+fn compilable(op: OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::Add | OpCode::Mul | OpCode::LessThan | OpCode::Equals
+            | OpCode::RelativeBase | OpCode::JumpIfTrue | OpCode::JumpIfFalse
+    )
+}
+
+// Builds one block's function. Every check that fails jumps to the exit for
+// the instruction being generated, which reports how far the block got.
+struct Generator<'a> {
+    b: FunctionBuilder<'a>,
+    context: Value,
+    mem: Value,
+    len: Value,
+    relative_base: Variable,
+    exit: Block,
+}
+
+impl Generator<'_> {
+    fn flags() -> MemFlags {
+        MemFlags::trusted()
+    }
+
+    // Continues in a fresh block when `failed` is zero.
+    fn check(&mut self, failed: Value) {
+        let next = self.b.create_block();
+
+        self.b.ins().brif(failed, self.exit, &[], next, &[]);
+        self.b.switch_to_block(next);
+    }
+
+    fn address(&mut self, raw: i64, mode: ParameterMode) -> Value {
+        let raw = self.b.ins().iconst(types::I64, raw);
+
+        let addr = match mode {
+            ParameterMode::Relative => {
+                let base = self.b.use_var(self.relative_base);
+                let (addr, overflow) = self.b.ins().sadd_overflow(base, raw);
+                self.check(overflow);
+                addr
+            },
+            _ => raw,
+        };
+
+        // Unsigned, so negative addresses fail too.
+        let outside = self.b.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, addr, self.len);
+        self.check(outside);
+        addr
+    }
+
+    fn cell(&mut self, addr: Value) -> Value {
+        let offset = self.b.ins().ishl_imm(addr, 3);
+
+        self.b.ins().iadd(self.mem, offset)
+    }
+
+    fn value(&mut self, raw: i64, mode: ParameterMode) -> Value {
+        if mode == ParameterMode::Immediate {
+            return self.b.ins().iconst(types::I64, raw);
+        }
+
+        let addr = self.address(raw, mode);
+        let cell = self.cell(addr);
+
+        self.b.ins().load(types::I64, Generator::flags(), cell, 0)
+    }
+
+    fn store(&mut self, raw: i64, mode: ParameterMode, value: Value) {
+        let addr = self.address(raw, mode);
+
+        // Leave before writing into compiled code, the interpreter does the
+        // write and drops whatever it invalidates.
+        let code_len = self.b.ins().load(types::I64, Generator::flags(), self.context, CODE_LEN);
+        let in_code = self.b.ins().icmp(IntCC::UnsignedLessThan, addr, code_len);
+        let (lookup, write) = (self.b.create_block(), self.b.create_block());
+
+        self.b.ins().brif(in_code, lookup, &[], write, &[]);
+        self.b.switch_to_block(lookup);
+
+        let code = self.b.ins().load(types::I64, Generator::flags(), self.context, CODE);
+        let byte = self.b.ins().iadd(code, addr);
+        let compiled = self.b.ins().uload8(types::I64, Generator::flags(), byte, 0);
+
+        self.b.ins().brif(compiled, self.exit, &[], write, &[]);
+        self.b.switch_to_block(write);
+
+        let cell = self.cell(addr);
+        self.b.ins().store(Generator::flags(), value, cell, 0);
+    }
+
+    // Writes back the relative base and the number of instructions done, and
+    // returns where the interpreter continues.
+    fn leave(&mut self, steps: usize, ic: Value) {
+        let base = self.b.use_var(self.relative_base);
+        let steps = self.b.ins().iconst(types::I64, steps as i64);
+
+        self.b.ins().store(Generator::flags(), base, self.context, RELATIVE_BASE);
+        self.b.ins().store(Generator::flags(), steps, self.context, STEPS);
+        self.b.ins().return_(&[ic]);
+    }
+
+    // Starts the code for instruction `n` at `addr`, with its own exit.
+    fn begin(&mut self, n: usize, addr: usize) {
+        let (exit, body) = (self.b.create_block(), self.b.create_block());
+
+        self.b.ins().jump(body, &[]);
+        self.b.switch_to_block(exit);
+        let ic = self.b.ins().iconst(types::I64, addr as i64);
+        self.leave(n, ic);
+
+        self.b.switch_to_block(body);
+        self.exit = exit;
+    }
+
+    fn instruction(&mut self, i: &Instruction, next: usize, n: usize) {
+        let raw = |k: usize| i.args[k].unwrap();
+
+        match i.op {
+            OpCode::Add | OpCode::Mul | OpCode::LessThan | OpCode::Equals => {
+                let a = self.value(raw(0), i.modes[0]);
+                let b = self.value(raw(1), i.modes[1]);
+
+                let result = match i.op {
+                    OpCode::Add | OpCode::Mul => {
+                        let (result, overflow) = if i.op == OpCode::Add {
+                            self.b.ins().sadd_overflow(a, b)
+                        } else {
+                            self.b.ins().smul_overflow(a, b)
+                        };
+                        self.check(overflow);
+                        result
+                    },
+                    _ => {
+                        let cc = if i.op == OpCode::LessThan { IntCC::SignedLessThan } else { IntCC::Equal };
+                        let flag = self.b.ins().icmp(cc, a, b);
+                        self.b.ins().uextend(types::I64, flag)
+                    },
+                };
+
+                self.store(raw(2), i.modes[2], result);
+            },
+            OpCode::RelativeBase => {
+                let offset = self.value(raw(0), i.modes[0]);
+                let base = self.b.use_var(self.relative_base);
+                let (base, overflow) = self.b.ins().sadd_overflow(base, offset);
+
+                self.check(overflow);
+                self.b.def_var(self.relative_base, base);
+            },
+            _ => {
+                let test = self.value(raw(0), i.modes[0]);
+                let target = self.value(raw(1), i.modes[1]);
+                let cc = if i.op == OpCode::JumpIfTrue { IntCC::NotEqual } else { IntCC::Equal };
+                let taken = self.b.ins().icmp_imm(cc, test, 0);
+                let (jump, fall) = (self.b.create_block(), self.b.create_block());
+
+                self.b.ins().brif(taken, jump, &[], fall, &[]);
+
+                // A negative target is a fault the interpreter reports.
+                self.b.switch_to_block(jump);
+                let negative = self.b.ins().icmp_imm(IntCC::SignedLessThan, target, 0);
+                self.check(negative);
+                self.leave(n + 1, target);
+
+                self.b.switch_to_block(fall);
+                let next = self.b.ins().iconst(types::I64, next as i64);
+                self.leave(n + 1, next);
+            },
+        }
+    }
+}
+
+impl Jit {
+    pub fn new(program: Vec<i64>, threshold: usize) -> Self {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").unwrap();
+
+        let isa = cranelift_native::builder()
+            .unwrap()
+            .finish(settings::Flags::new(flags))
+            .unwrap();
+
+        let mut cpu = IntCode::new(Memory::new(program));
+        let dirty = cpu.observe(Dirty::default());
+
+        Jit {
+            cpu,
+            module: JITModule::new(JITBuilder::with_isa(isa, default_libcall_names())),
+            blocks: HashMap::new(),
+            counts: HashMap::new(),
+            rejected: HashSet::new(),
+            code: Vec::new(),
+            dirty,
+            threshold,
+            stats: Stats::default(),
+        }
+    }
+
+    // The instructions a block at `start` would cover.
+    fn decode(&self, start: usize) -> Vec<(usize, Instruction)> {
+        let memory = self.cpu.mem.bucket.borrow();
+        let mut block = Vec::new();
+        let mut addr = start;
+
+        while block.len() < MAX_BLOCK && addr < memory.len() {
+            let label = memory[addr];
+            let mut i = Instruction::new(label);
+
+            if !compilable(i.op) || Instruction::illegal(label, addr).is_some() || addr + i.len > memory.len() {
+                break;
+            }
+
+            for n in 0..i.len - 1 {
+                i.args[n] = Some(memory[addr + 1 + n]);
+            }
+
+            let jump = matches!(i.op, OpCode::JumpIfTrue | OpCode::JumpIfFalse);
+            let len = i.len;
+
+            block.push((addr, i));
+            addr += len;
+
+            if jump {
+                break;
+            }
+        }
+
+        block
+    }
+
+    fn compile(&mut self, start: usize) -> Option<Compiled> {
+        let block = self.decode(start);
+        let (last, _) = block.last()?;
+        let end = last + block.last()?.1.len;
+
+        let pointer = self.module.target_config().pointer_type();
+        let mut context = self.module.make_context();
+        let mut builder = FunctionBuilderContext::new();
+
+        context.func.signature.params.push(AbiParam::new(pointer));
+        context.func.signature.returns.push(AbiParam::new(types::I64));
+
+        {
+            let mut b = FunctionBuilder::new(&mut context.func, &mut builder);
+            let entry = b.create_block();
+
+            b.append_block_params_for_function_params(entry);
+            b.switch_to_block(entry);
+
+            let cx = b.block_params(entry)[0];
+            let mem = b.ins().load(pointer, Generator::flags(), cx, MEM);
+            let len = b.ins().load(types::I64, Generator::flags(), cx, LEN);
+            let base = b.ins().load(types::I64, Generator::flags(), cx, RELATIVE_BASE);
+            let relative_base = Variable::from_u32(0);
+
+            b.declare_var(relative_base, types::I64);
+            b.def_var(relative_base, base);
+
+            let mut gen = Generator { b, context: cx, mem, len, relative_base, exit: entry };
+
+            for (n, (addr, i)) in block.iter().enumerate() {
+                gen.begin(n, *addr);
+                gen.instruction(i, addr + i.len, n);
+            }
+
+            if !matches!(block.last()?.1.op, OpCode::JumpIfTrue | OpCode::JumpIfFalse) {
+                let next = gen.b.ins().iconst(types::I64, end as i64);
+                gen.leave(block.len(), next);
+            }
+
+            gen.b.seal_all_blocks();
+            gen.b.finalize();
+        }
+
+        let name = format!("block_{}_{}", start, self.stats.compiled);
+        let id = self.module.declare_function(&name, Linkage::Local, &context.func.signature).ok()?;
+
+        self.module.define_function(id, &mut context).ok()?;
+        self.module.clear_context(&mut context);
+        self.module.finalize_definitions().ok()?;
+
+        let native = unsafe { std::mem::transmute::<*const u8, Native>(self.module.get_finalized_function(id)) };
+
+        Some(Compiled { native, start, end, len: block.len() })
+    }
+
+    fn mark(&mut self) {
+        self.code.iter_mut().for_each(|c| *c = 0);
+
+        for block in self.blocks.values() {
+            if self.code.len() < block.end {
+                self.code.resize(block.end, 0);
+            }
+            self.code[block.start..block.end].iter_mut().for_each(|c| *c = 1);
+        }
+    }
+
+    // Drops every block the interpreter wrote into since the last call.
+    fn invalidate(&mut self) {
+        let writes = std::mem::take(&mut self.dirty.borrow_mut().0);
+        let hit = writes.iter().any(|addr| self.code.get(*addr).is_some_and(|c| *c != 0));
+
+        if !hit {
+            return;
+        }
+
+        let before = self.blocks.len();
+
+        self.blocks.retain(|_, b| !writes.iter().any(|addr| (b.start..b.end).contains(addr)));
+        self.stats.deoptimized += before - self.blocks.len();
+        self.counts.clear();
+        self.rejected.clear();
+        self.mark();
+    }
+
+    // Returns how many instructions the block got through.
+    fn enter(&mut self, start: usize) -> usize {
+        let native = self.blocks[&start].native;
+        let mut memory = self.cpu.mem.bucket.borrow_mut();
+        let mut context = Context {
+            mem: memory.as_mut_ptr(),
+            len: memory.len() as i64,
+            code: self.code.as_ptr(),
+            code_len: self.code.len() as i64,
+            relative_base: self.cpu.relative_base as i64,
+            steps: 0,
+        };
+
+        // The block only touches memory inside `len` and the code map inside
+        // `code_len`, both of which outlive the call.
+        let next = unsafe { native(&mut context) };
+
+        if context.steps > 0 {
+            self.cpu.ic = next as usize;
+            self.cpu.relative_base = context.relative_base as usize;
+            self.cpu.steps += context.steps as usize;
+        }
+
+        context.steps as usize
+    }
+
+    // Runs to completion like `IntCode::try_run`, with the same output and
+    // faults.
+    pub fn run(&mut self, input: &mut Vec<i64>, limit: usize) -> Result<Vec<i64>, Fault> {
+        let mut output = Vec::new();
+
+        loop {
+            self.invalidate();
+
+            let ic = self.cpu.ic;
+
+            match self.blocks.get(&ic).map(|b| b.len) {
+                // Only when the whole block fits in the step limit, so a
+                // runaway program stops at the same step.
+                Some(len) if self.cpu.steps + len <= limit => {
+                    let steps = self.enter(ic);
+
+                    self.stats.native_steps += steps;
+                    if steps > 0 {
+                        continue;
+                    }
+                },
+                Some(_) => (),
+                None if !self.rejected.contains(&ic) => {
+                    let count = self.counts.entry(ic).or_insert(0);
+
+                    *count += 1;
+                    if *count >= self.threshold {
+                        match self.compile(ic) {
+                            Some(block) => {
+                                self.blocks.insert(ic, block);
+                                self.stats.compiled += 1;
+                                self.mark();
+                                continue;
+                            },
+                            None => {
+                                self.rejected.insert(ic);
+                            },
+                        }
+                    }
+                },
+                None => (),
+            }
+
+            if !self.cpu.try_step(input, &mut output, limit)? {
+                return Ok(output);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testcase;
+    use std::path::Path;
+
+    #[test]
+    fn matches_the_interpreter_on_the_cases() {
+        let cases = testcase::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("cases")).unwrap();
+
+        for case in cases.iter().filter(|c| !c.strict) {
+            let limit = case.limit.unwrap_or(100_000);
+            let mut jit = Jit::new(case.program.clone(), 1);
+            let mut intcode = IntCode::new(Memory::new(case.program.clone()));
+
+            let expected = intcode.try_run(&mut case.input.clone(), limit);
+
+            assert_eq!(jit.run(&mut case.input.clone(), limit), expected, "{}", case.name);
+            assert_eq!(jit.cpu.mem.snapshot(), intcode.mem.snapshot(), "{}", case.name);
+            assert_eq!(jit.cpu.steps, intcode.steps, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn deoptimizes_on_self_modification() {
+        // counts up in steps of one until 5, then patches the step to 10
+        let program = vec![
+            1001,100,1,100, 1008,100,5,101, 1006,101,15,
+            1101,0,10,2,
+            1007,100,50,102, 1005,102,0,
+            4,100, 99,
+        ];
+
+        for threshold in [1, 2].iter() {
+            let mut jit = Jit::new(program.clone(), *threshold);
+
+            assert_eq!(jit.run(&mut vec![], 1000), Ok(vec![55]));
+            assert_eq!(jit.stats.deoptimized, 1);
+            assert!(jit.stats.native_steps > 0);
+        }
+    }
+}
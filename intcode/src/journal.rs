@@ -0,0 +1,197 @@
+use super::observer::Observer;
+use super::IntCode;
+
+// Everything needed to undo one instruction: the registers as they were before
+// it was decoded and the old value of every cell it wrote.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub step: usize,
+    pub ic: usize,
+    pub relative_base: usize,
+    pub writes: Vec<(usize, i64)>,
+    pub input: Option<i64>,
+    pub output: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Journal {
+    pub entries: Vec<Entry>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Journal::default()
+    }
+}
+
+impl Observer for Journal {
+    fn begin(&mut self, step: usize, ic: usize, relative_base: usize) {
+        self.entries.push(Entry { step, ic, relative_base, ..Default::default() });
+    }
+
+    fn write(&mut self, addr: usize, old: i64, _new: i64) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.writes.push((addr, old));
+        }
+    }
+
+    fn input(&mut self, value: i64) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.input = Some(value);
+        }
+    }
+
+    fn output(&mut self, value: i64) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.output = Some(value);
+        }
+    }
+}
+
+impl IntCode {
+    // Starts keeping a journal unless there already is one.
+    pub fn keep_journal(&mut self) {
+        if self.journal.is_none() {
+            self.journal = Some(self.observe(Journal::new()));
+        }
+    }
+
+    // Undoes the last executed instruction, the caller is handed the entry so it
+    // can give back consumed input and drop produced output.
+    pub fn step_back(&mut self) -> Option<Entry> {
+        let entry = self.journal.as_ref()?.borrow_mut().entries.pop()?;
+
+        // The old values came from the backing store, so they go straight back
+        // there without touching any device mapped over it.
+        for (addr, old) in entry.writes.iter().rev() {
+            self.mem.poke(*addr, *old);
+        }
+
+        self.ic = entry.ic;
+        self.relative_base = entry.relative_base;
+        self.steps = entry.step - 1;
+
+        if let Some(replay) = self.replay.as_ref() {
+            replay.borrow_mut().truncate(self.steps);
+        }
+
+        Some(entry)
+    }
+
+    pub fn back_to_write(&mut self, addr: usize) -> Vec<Entry> {
+        let mut undone = Vec::new();
+
+        while let Some(entry) = self.step_back() {
+            let hit = entry.writes.iter().any(|(a, _)| *a == addr);
+
+            undone.push(entry);
+            if hit {
+                break;
+            }
+        }
+
+        undone
+    }
+
+    pub fn rewind(&mut self, step: usize) -> Vec<Entry> {
+        let mut undone = Vec::new();
+
+        while self.steps > step {
+            match self.step_back() {
+                Some(entry) => undone.push(entry),
+                None => break,
+            }
+        }
+
+        undone
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Memory;
+    use super::super::device::Console;
+
+    fn journaled(buf: &[i64]) -> IntCode {
+        let memory = Memory::new(buf.to_vec());
+        let mut intcode = IntCode::new(memory);
+        intcode.keep_journal();
+
+        intcode
+    }
+
+    #[test]
+    fn rewind_to_start() {
+        let buf = vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
+        let mut intcode = journaled(&buf);
+
+        intcode.run_program(&mut vec![]);
+        assert_eq!(intcode.mem.read(100), 16);
+
+        let undone = intcode.rewind(0);
+
+        assert_eq!(undone.len(), 80);
+        assert_eq!(undone.iter().filter(|e| e.output.is_some()).count(), 16);
+        assert_eq!((intcode.ic, intcode.relative_base, intcode.steps), (0, 0, 0));
+        assert_eq!(intcode.mem.read(100), 0);
+        assert_eq!(intcode.mem.read(101), 0);
+        assert_eq!(intcode.run_program(&mut vec![]), buf);
+    }
+
+    #[test]
+    fn back_to_previous_write() {
+        let buf = vec![3,9,8,9,10,9,4,9,99,-1,8];
+        let mut intcode = journaled(&buf);
+
+        intcode.run_program(&mut vec![8]);
+        assert_eq!(intcode.mem.read(9), 1);
+
+        let undone = intcode.back_to_write(9);
+
+        assert_eq!(undone.len(), 2);
+        assert_eq!(intcode.ic, 2);
+        assert_eq!(intcode.mem.read(9), 8);
+
+        intcode.back_to_write(9);
+
+        assert_eq!(intcode.ic, 0);
+        assert_eq!(intcode.mem.read(9), -1);
+        assert_eq!(intcode.step_back(), None);
+    }
+
+    #[test]
+    fn clones_keep_their_own_history() {
+        let buf = vec![3,9,8,9,10,9,4,9,99,-1,8];
+        let mut intcode = journaled(&buf);
+        intcode.record();
+
+        intcode.run_program(&mut vec![8]);
+
+        let mut snapshot = intcode.clone();
+
+        assert_eq!(snapshot.rewind(0).len(), 3);
+        assert_eq!(snapshot.replay.as_ref().map(|r| r.borrow().events.len()), Some(0));
+        assert_eq!(intcode.journal.as_ref().map(|j| j.borrow().entries.len()), Some(3));
+        assert_eq!(intcode.replay.as_ref().map(|r| r.borrow().events.len()), Some(2));
+
+        snapshot.run_program(&mut vec![7]);
+        assert_eq!(snapshot.replay.as_ref().map(|r| r.borrow().to_string()).as_deref(), Some("in 1 7\nout 3 0\n"));
+        assert_eq!(intcode.journal.as_ref().map(|j| j.borrow().entries.len()), Some(3));
+    }
+
+    #[test]
+    fn back_over_a_device_write() {
+        let mut memory = Memory::new(vec![1101,0,111,1000,99]);
+        let console = memory.map(1000..1001, Console::default()).unwrap();
+        let mut intcode = IntCode::new(memory);
+        intcode.keep_journal();
+
+        intcode.run_program(&mut vec![]);
+        assert!(intcode.step_back().is_some());
+
+        assert_eq!(console.borrow().output, vec![111]);
+        assert_eq!(intcode.mem.peek(1000), 0);
+        assert_eq!(intcode.ic, 0);
+    }
+}
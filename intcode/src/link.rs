@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use itertools::Itertools;
+
+use super::Instruction;
+
+// A separately assembled module whose addresses all start from 0, written as:
+//
+//     module main
+//     code 21101,7,0,0, 1105,1,0, 99
+//     define start 0
+//     local 1
+//     extern 6 double
+//
+// `local` marks a word holding an address inside the module, moved along with
+// it. `extern` marks a word that gets the address of a symbol from any module
+// added to it, so a word holding 2 there means two cells past the symbol.
+// `code` lines append, and a file may hold several modules. Lines starting
+// with `#` are comments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Object {
+    pub name: String,
+    pub code: Vec<i64>,
+    pub symbols: BTreeMap<String, usize>,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    Local,
+    Symbol(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relocation {
+    pub at: usize,
+    pub target: Target,
+}
+
+impl Object {
+    pub fn new(name: &str) -> Self {
+        Object {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Defines `symbol` at the next word to be emitted. Along with `emit` and
+    // `data` this builds an object from Rust, the binary itself only parses them.
+    #[allow(dead_code)]
+    pub fn define(&mut self, symbol: &str) {
+        self.symbols.insert(symbol.to_string(), self.code.len());
+    }
+
+    // Appends an instruction. `targets` pairs operand numbers with what they
+    // refer to, the operand itself is the offset from there.
+    #[allow(dead_code)]
+    pub fn emit(&mut self, instruction: Instruction, targets: &[(usize, Target)]) {
+        let start = self.code.len();
+
+        self.code.extend(instruction.encode());
+        for (n, target) in targets.iter() {
+            self.relocations.push(Relocation { at: start + 1 + n, target: target.clone() });
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn data(&mut self, words: &[i64]) {
+        self.code.extend(words);
+    }
+
+    pub fn load(path: &Path) -> io::Result<Vec<Object>> {
+        parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "module {}", self.name)?;
+        writeln!(f, "code {}", self.code.iter().join(","))?;
+
+        for (symbol, at) in self.symbols.iter() {
+            writeln!(f, "define {} {}", symbol, at)?;
+        }
+
+        for r in self.relocations.iter() {
+            match &r.target {
+                Target::Local => writeln!(f, "local {}", r.at)?,
+                Target::Symbol(s) => writeln!(f, "extern {} {}", r.at, s)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<Object>, String> {
+    let mut objects = Vec::<Object>::new();
+
+    for (n, line) in text.lines().enumerate().map(|(n, l)| (n + 1, l.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parts = line.split_whitespace().collect::<Vec<&str>>();
+        let offset = |i: usize| parts.get(i)
+            .and_then(|p| p.parse::<usize>().ok())
+            .ok_or_else(|| format!("line {}: bad offset in {}", n, line));
+        let name = |i: usize| parts.get(i)
+            .map(|p| p.to_string())
+            .ok_or_else(|| format!("line {}: missing name in {}", n, line));
+
+        if parts[0] == "module" {
+            objects.push(Object::new(&name(1)?));
+            continue;
+        }
+
+        let object = objects.last_mut().ok_or_else(|| format!("line {}: {} before any module", n, parts[0]))?;
+
+        match parts[0] {
+            "code" => {
+                for word in line[4..].split(|c: char| c == ',' || c.is_whitespace()).filter(|w| !w.is_empty()) {
+                    object.code.push(word.parse::<i64>().map_err(|_| format!("line {}: bad number {}", n, word))?);
+                }
+            },
+            "define" => {
+                object.symbols.insert(name(1)?, offset(2)?);
+            },
+            "local" => object.relocations.push(Relocation { at: offset(1)?, target: Target::Local }),
+            "extern" => object.relocations.push(Relocation { at: offset(1)?, target: Target::Symbol(name(2)?) }),
+            key => return Err(format!("line {}: unknown key {}", n, key)),
+        }
+    }
+
+    Ok(objects)
+}
+
+#[derive(Debug)]
+pub struct Linked {
+    pub image: Vec<i64>,
+    pub symbols: BTreeMap<String, usize>,
+    // Where each module ended up.
+    pub bases: Vec<(String, usize)>,
+}
+
+// Lays the modules out one after another in the order given, so the first one
+// holds the entry point at 0, and patches every relocation.
+pub fn link(objects: &[Object]) -> Result<Linked, String> {
+    let mut linked = Linked { image: Vec::new(), symbols: BTreeMap::new(), bases: Vec::new() };
+
+    for object in objects.iter() {
+        let base = linked.image.len();
+
+        for (symbol, at) in object.symbols.iter() {
+            if *at > object.code.len() {
+                return Err(format!("{}: {} is defined past the end of the module", object.name, symbol));
+            }
+            if linked.symbols.insert(symbol.clone(), base + at).is_some() {
+                return Err(format!("{}: {} is already defined", object.name, symbol));
+            }
+        }
+
+        linked.bases.push((object.name.clone(), base));
+        linked.image.extend(object.code.iter());
+    }
+
+    for (object, (_, base)) in objects.iter().zip(linked.bases.iter()) {
+        for r in object.relocations.iter() {
+            if r.at >= object.code.len() {
+                return Err(format!("{}: relocation at {} is outside the module", object.name, r.at));
+            }
+
+            let address = match &r.target {
+                Target::Local => *base,
+                Target::Symbol(s) => *linked.symbols.get(s)
+                    .ok_or_else(|| format!("{}: undefined symbol {}", object.name, s))?,
+            };
+
+            let word = &mut linked.image[base + r.at];
+
+            *word = word.checked_add(address as i64)
+                .ok_or_else(|| format!("{}: relocation at {} overflows", object.name, r.at))?;
+        }
+    }
+
+    Ok(linked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::builder::Operand::*;
+    use super::super::{IntCode, Memory};
+
+    // main reads a number, calls double and prints the result.
+    fn modules() -> Vec<Object> {
+        let mut main = Object::new("main");
+
+        main.emit(Instruction::relative_base(Immediate(0)).unwrap(), &[(0, Target::Symbol(String::from("stack")))]);
+        main.emit(Instruction::input(Relative(1)).unwrap(), &[]);
+        main.emit(Instruction::add(Immediate(11), Immediate(0), Relative(0)).unwrap(), &[(0, Target::Local)]);
+        main.emit(Instruction::jump_if_true(Immediate(1), Immediate(0)).unwrap(), &[(1, Target::Symbol(String::from("double")))]);
+        main.emit(Instruction::output(Position(0)).unwrap(), &[(0, Target::Symbol(String::from("result")))]);
+        main.emit(Instruction::halt().unwrap(), &[]);
+
+        let mut lib = Object::new("lib");
+
+        lib.define("double");
+        lib.emit(Instruction::mul(Relative(1), Immediate(2), Position(0)).unwrap(), &[(2, Target::Symbol(String::from("result")))]);
+        lib.emit(Instruction::jump_if_true(Immediate(1), Relative(0)).unwrap(), &[]);
+        lib.define("result");
+        lib.data(&[0]);
+        lib.define("stack");
+
+        vec![main, lib]
+    }
+
+    #[test]
+    fn links_and_runs() {
+        let linked = link(&modules()).unwrap();
+
+        assert_eq!(linked.bases, vec![(String::from("main"), 0), (String::from("lib"), 14)]);
+        assert_eq!(linked.symbols["result"], 21);
+        assert_eq!(IntCode::new(Memory::new(linked.image)).run_program(&mut vec![21]), vec![42]);
+    }
+
+    #[test]
+    fn text_round_trip_and_errors() {
+        let objects = modules();
+        let text = objects.iter().map(|o| o.to_string()).join("\n# next module\n");
+
+        assert_eq!(parse(&text), Ok(objects.clone()));
+
+        assert_eq!(link(&objects[..1]).err().as_deref(), Some("main: undefined symbol stack"));
+        assert_eq!(link(&[objects[1].clone(), objects[1].clone()]).err().as_deref(), Some("lib: double is already defined"));
+        assert_eq!(parse("code 1,2").err().as_deref(), Some("line 1: code before any module"));
+
+        let mut pad = Object::new("pad");
+        let mut big = Object::new("big");
+
+        pad.data(&[0]);
+        big.data(&[0, i64::MAX]);
+        big.relocations.push(Relocation { at: 1, target: Target::Local });
+
+        assert_eq!(link(&[big.clone()]).map(|l| l.image), Ok(vec![0, i64::MAX]));
+        assert_eq!(link(&[pad, big]).err().as_deref(), Some("big: relocation at 1 overflows"));
+    }
+}
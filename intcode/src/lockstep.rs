@@ -0,0 +1,183 @@
+use std::fmt;
+
+use super::observer::Observer;
+use super::{IntCode, Instruction, OpCode};
+
+// What one instruction did, as seen from outside the engine.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Step {
+    pub ic: usize,
+    pub relative_base: usize,
+    pub writes: Vec<(usize, i64)>,
+    pub output: Option<i64>,
+}
+
+// Why an engine didn't take a step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stopped {
+    Halted,
+    NeedInput,
+    Fault,
+}
+
+// Anything that can run Intcode one instruction at a time.
+pub trait Engine {
+    fn step(&mut self, input: &mut Vec<i64>) -> Result<Step, Stopped>;
+    fn read(&self, addr: usize) -> i64;
+}
+
+// The cells one instruction wrote.
+#[derive(Default)]
+struct Writes(Vec<usize>);
+
+impl Observer for Writes {
+    fn write(&mut self, addr: usize, _old: i64, _new: i64) {
+        self.0.push(addr);
+    }
+}
+
+impl Engine for IntCode {
+    fn step(&mut self, input: &mut Vec<i64>) -> Result<Step, Stopped> {
+        match Instruction::new(self.mem.peek(self.ic)).op {
+            OpCode::Halt => return Err(Stopped::Halted),
+            OpCode::Unknown => return Err(Stopped::Fault),
+            OpCode::Input if input.is_empty() => return Err(Stopped::NeedInput),
+            _ => (),
+        }
+
+        // Attached for this instruction only, so nothing piles up over a long run.
+        let writes = self.observe(Writes::default());
+        let output = self.next().and_then(|i| self.execute(i, input));
+
+        self.unobserve(&writes);
+
+        if self.fault.is_some() {
+            return Err(Stopped::Fault);
+        }
+
+        // Reading a mapped device back could consume or change it, so only
+        // the backing store is compared.
+        let writes = writes.borrow().0.iter().map(|addr| (*addr, self.mem.peek(*addr))).collect();
+
+        Ok(Step {
+            ic: self.ic,
+            relative_base: self.relative_base,
+            writes,
+            output,
+        })
+    }
+
+    fn read(&self, addr: usize) -> i64 {
+        self.mem.peek(addr)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub step: usize,
+    pub what: &'static str,
+    pub left: String,
+    pub right: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "step {}: {} differs, {} vs {}", self.step, self.what, self.left, self.right)
+    }
+}
+
+fn compare<T: PartialEq + fmt::Debug>(step: usize, what: &'static str, left: T, right: T) -> Result<(), Mismatch> {
+    if left == right {
+        return Ok(());
+    }
+
+    Err(Mismatch { step, what, left: format!("{:?}", left), right: format!("{:?}", right) })
+}
+
+// Runs both engines on the same input one instruction at a time and stops at the
+// first step where they disagree, stopping for different reasons counts. Returns
+// the number of steps both agreed on.
+pub fn lockstep(left: &mut impl Engine, right: &mut impl Engine, input: &[i64], limit: usize) -> Result<usize, Mismatch> {
+    let mut left_input = input.to_vec();
+    let mut right_input = input.to_vec();
+
+    for n in 1..=limit {
+        let (l, r) = match (left.step(&mut left_input), right.step(&mut right_input)) {
+            (Ok(l), Ok(r)) => (l, r),
+            (Err(l), Err(r)) if l == r => return Ok(n - 1),
+            (l, r) => return compare(n, "stop", l.err(), r.err()).map(|_| n),
+        };
+
+        compare(n, "ic", l.ic, r.ic)?;
+        compare(n, "relative base", l.relative_base, r.relative_base)?;
+        compare(n, "writes", &l.writes, &r.writes)?;
+        compare(n, "output", l.output, r.output)?;
+    }
+
+    Ok(limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::device::Console;
+    use super::super::predecode::Predecoded;
+    use super::super::Memory;
+
+    fn intcode(program: &[i64]) -> IntCode {
+        IntCode::new(Memory::new(program.to_vec()))
+    }
+
+    #[test]
+    fn engines_agree() {
+        let quine = [109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
+        // turns the add at 8 into a multiply before reaching it
+        let modifying = [1101,1,1,20,1101,0,1102,8,1101,3,4,20,4,20,99];
+
+        assert_eq!(lockstep(&mut intcode(&quine), &mut Predecoded::new(&quine), &[], 10_000), Ok(80));
+        let mut predecoded = Predecoded::new(&modifying);
+
+        assert_eq!(lockstep(&mut intcode(&modifying), &mut predecoded, &[], 100), Ok(4));
+        assert_eq!(predecoded.read(20), 12);
+    }
+
+    #[test]
+    fn keeps_no_history_and_leaves_devices_alone() {
+        let quine = [109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
+        let mut left = intcode(&quine);
+
+        left.keep_journal();
+        assert_eq!(lockstep(&mut left, &mut intcode(&quine), &[], 10_000), Ok(80));
+        assert_eq!(left.journal.as_ref().map(|j| j.borrow().entries.len()), Some(80));
+        assert_eq!(format!("{:?}", left.observers), "Observers(1)");
+
+        let console = || {
+            let mut memory = Memory::new(vec![1101,0,111,1000, 99]);
+            let console = memory.map(1000..1001, Console::default()).unwrap();
+            console.borrow_mut().input.push_back(5);
+            (IntCode::new(memory), console)
+        };
+        let ((mut left, a), (mut right, b)) = (console(), console());
+
+        assert_eq!(lockstep(&mut left, &mut right, &[], 100), Ok(1));
+        assert_eq!((a.borrow().input.len(), b.borrow().input.len()), (1, 1));
+        assert_eq!(a.borrow().output, vec![111]);
+    }
+
+    #[test]
+    fn reports_first_divergence() {
+        let program = [3,9,8,9,10,9,4,9,99,-1,8];
+        let mut other = program.to_vec();
+        other[4] = 11;
+
+        let mismatch = lockstep(&mut intcode(&program), &mut intcode(&other), &[8], 100).unwrap_err();
+
+        assert_eq!(mismatch.step, 2);
+        assert_eq!(mismatch.to_string(), "step 2: writes differs, [(9, 1)] vs [(9, 0)]");
+
+        // one halts where the other wants input
+        let mismatch = lockstep(&mut intcode(&[99]), &mut Predecoded::new(&[3,0,99]), &[], 100).unwrap_err();
+
+        assert_eq!(mismatch.to_string(), "step 1: stop differs, Some(Halted) vs Some(NeedInput)");
+    }
+}
@@ -1,22 +1,75 @@
 extern crate itertools;
 
+mod aot;
+mod builder;
+mod callstack;
+mod compiler;
+mod coverage;
+mod decompile;
+mod debugger;
+mod device;
+mod disasm;
+mod expect;
+mod fuzz;
+mod inspect;
+#[cfg(feature = "jit")]
+mod jit;
+mod journal;
+mod link;
+mod lockstep;
+mod maze;
+mod observer;
+mod optimize;
+mod predecode;
+mod profile;
+mod replay;
+mod robot;
+mod screen;
+mod symbolic;
+mod testcase;
+#[cfg(feature = "tui")]
+mod tui;
+
 use itertools::Itertools;
 
+use device::Devices;
+use journal::Journal;
+use observer::Observers;
+use profile::Profile;
+use replay::Replay;
+
 use std::cell::RefCell;
 use std::env::args;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::SeekFrom;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::process::exit;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct Memory {
     bucket: RefCell<Vec<i64>>,
+    devices: Devices,
 }
 
 impl Memory {
+    fn new(program: Vec<i64>) -> Self {
+        Memory {
+            bucket: RefCell::new(program),
+            devices: Devices::default(),
+        }
+    }
+
     fn read(&self, index: usize) -> i64 {
+        match self.devices.find(index) {
+            Some((device, offset)) => device.borrow_mut().read(offset),
+            None => self.peek(index),
+        }
+    }
+
+    // Reads the backing store only, mapped devices are never touched.
+    fn peek(&self, index: usize) -> i64 {
         if self.bucket.borrow().len() <= index {
             0
         } else {
@@ -24,7 +77,21 @@ impl Memory {
         }
     }
 
+    fn snapshot(&self) -> Vec<i64> {
+        self.bucket.borrow().clone()
+    }
+
     fn write(&self, index: usize, value: i64) {
+        if let Some((device, offset)) = self.devices.find(index) {
+            device.borrow_mut().write(offset, value);
+            return;
+        }
+
+        self.poke(index, value);
+    }
+
+    // Writes the backing store only, the counterpart of `peek`.
+    fn poke(&self, index: usize, value: i64) {
         if self.bucket.borrow().len() <= index {
             self.bucket.borrow_mut().resize_with(index + 1, {|| 0 as i64});
             self.bucket.borrow_mut()[index] = value;
@@ -34,7 +101,7 @@ impl Memory {
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 enum OpCode {
     Add,
     Mul,
@@ -67,7 +134,25 @@ impl From<i64> for OpCode {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+impl OpCode {
+    fn code(self) -> i64 {
+        match self {
+            OpCode::Add => 1,
+            OpCode::Mul => 2,
+            OpCode::Input => 3,
+            OpCode::Output => 4,
+            OpCode::JumpIfTrue => 5,
+            OpCode::JumpIfFalse => 6,
+            OpCode::LessThan => 7,
+            OpCode::Equals => 8,
+            OpCode::RelativeBase => 9,
+            OpCode::Halt => 99,
+            OpCode::Unknown => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum ParameterMode {
     Position,
     Immediate,
@@ -84,6 +169,16 @@ impl From<i64> for ParameterMode {
     }
 }
 
+impl ParameterMode {
+    fn code(&self) -> i64 {
+        match self {
+            ParameterMode::Position => 0,
+            ParameterMode::Immediate => 1,
+            ParameterMode::Relative => 2,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug)]
 struct Instruction {
     op: OpCode,
@@ -134,6 +229,32 @@ impl Instruction {
         (label, parts[2], parts[1], parts[0])
     }
 
+    // Which operand, if any, the instruction writes through.
+    fn destination(&self) -> Option<usize> {
+        match self.op {
+            OpCode::Add | OpCode::Mul | OpCode::LessThan | OpCode::Equals => Some(2),
+            OpCode::Input => Some(0),
+            _ => None,
+        }
+    }
+
+    // What strict decoding objects to in a label: a mode digit other than 0-2, or
+    // an immediate-mode destination.
+    fn illegal(label: i64, addr: usize) -> Option<Fault> {
+        let instruction = Instruction::new(label);
+        let (_, m1, m2, m3) = Self::process_label(label);
+        let arity = instruction.len.saturating_sub(1);
+
+        if let Some(mode) = [m1, m2, m3].iter().take(arity).find(|m| !(0..=2).contains(*m)) {
+            return Some(Fault::IllegalMode { addr, mode: *mode });
+        }
+
+        match instruction.destination() {
+            Some(n) if instruction.modes[n] == ParameterMode::Immediate => Some(Fault::ImmediateWrite { addr }),
+            _ => None,
+        }
+    }
+
     fn len(op: &OpCode) -> usize {
         match op {
             OpCode::Add => 4,
@@ -151,35 +272,152 @@ impl Instruction {
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+enum Status {
+    Output(i64),
+    NeedInput,
+    Halted,
+}
+
+// Ways a run can go wrong that a caller may want to handle instead of dying on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Fault {
+    InvalidOpCode { value: i64, addr: usize },
+    NeedInput { addr: usize },
+    StepLimit { steps: usize },
+    BadAddress { addr: i64 },
+    MemoryLimit { addr: usize },
+    Overflow,
+    IllegalMode { addr: usize, mode: i64 },
+    ImmediateWrite { addr: usize },
+}
+
+// How decoding treats labels the spec doesn't allow. `Lenient` is the original
+// behavior: unknown modes read as position mode and immediate destinations are
+// written as positions.
+#[derive(Clone, Copy, Debug)]
+enum Policy {
+    Lenient,
+    Warn(fn(&Fault)),
+    Strict,
+}
+
+impl Fault {
+    fn name(&self) -> &'static str {
+        match self {
+            Fault::InvalidOpCode { .. } => "invalid-opcode",
+            Fault::NeedInput { .. } => "need-input",
+            Fault::StepLimit { .. } => "step-limit",
+            Fault::BadAddress { .. } => "bad-address",
+            Fault::MemoryLimit { .. } => "memory-limit",
+            Fault::Overflow => "overflow",
+            Fault::IllegalMode { .. } => "illegal-mode",
+            Fault::ImmediateWrite { .. } => "immediate-write",
+        }
+    }
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Fault::InvalidOpCode { value, addr } => write!(f, "Invalid OpCode: {} at position {}", value, addr),
+            Fault::NeedInput { addr } => write!(f, "Out of input at position {}", addr),
+            Fault::StepLimit { steps } => write!(f, "Still running after {} steps", steps),
+            Fault::BadAddress { addr } => write!(f, "Negative address: {}", addr),
+            Fault::MemoryLimit { addr } => write!(f, "Write to {} is past the memory limit", addr),
+            Fault::Overflow => write!(f, "Arithmetic overflow"),
+            Fault::IllegalMode { addr, mode } => write!(f, "Illegal parameter mode {} at position {}", mode, addr),
+            Fault::ImmediateWrite { addr } => write!(f, "Write through an immediate operand at position {}", addr),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct IntCode {
     mem: Memory,
     ic: usize,
+    // Where the instruction last fetched started, `ic` has moved past it by
+    // the time it executes.
+    last_ic: usize,
     relative_base: usize,
+    steps: usize,
+    observers: Observers,
+    // Handles to the replay and journal observers, when attached, for
+    // winding them back along with the machine.
+    replay: Option<Rc<RefCell<Replay>>>,
+    journal: Option<Rc<RefCell<Journal>>>,
+    memory_limit: Option<usize>,
+    fault: Option<Fault>,
+    policy: Policy,
+}
+
+// A clone reports to the same observers, except the journal and the replay: they
+// hold this machine's history, so the clone carries on with copies of them.
+impl Clone for IntCode {
+    fn clone(&self) -> Self {
+        let mut cpu = IntCode {
+            mem: self.mem.clone(),
+            ic: self.ic,
+            last_ic: self.last_ic,
+            relative_base: self.relative_base,
+            steps: self.steps,
+            observers: self.observers.clone(),
+            replay: None,
+            journal: None,
+            memory_limit: self.memory_limit,
+            fault: self.fault.clone(),
+            policy: self.policy,
+        };
+
+        if let Some(journal) = self.journal.as_ref() {
+            cpu.unobserve(journal);
+            cpu.journal = Some(cpu.observe(journal.borrow().clone()));
+        }
+        if let Some(replay) = self.replay.as_ref() {
+            cpu.unobserve(replay);
+            cpu.replay = Some(cpu.observe(replay.borrow().clone()));
+        }
+
+        cpu
+    }
 }
 
 impl Iterator for IntCode {
     type Item = Instruction;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut instruction = Instruction::new(self.mem.read(self.ic));
+        if self.fault.is_some() {
+            return None;
+        }
+
+        self.last_ic = self.ic;
+
+        let mut instruction = Instruction::new(self.mem.peek(self.ic));
+
+        for i in 0..instruction.len.saturating_sub(1) {
+            instruction.args[i] = Some(self.mem.peek(self.ic + (i + 1)));
+        }
+
+        self.observers.fetch(self.ic, &instruction);
 
         match &instruction.op {
             OpCode::Halt => None,
             OpCode::Unknown => {
                 println!(
                     "Invalid OpCode: {} at position {}",
-                    self.mem.read(self.ic),
+                    self.mem.peek(self.ic),
                     self.ic
                 );
                 exit(1);
             }
             _ => {
-                for i in 0..(instruction.len - 1) {
-                    instruction.args[i] = Some(self.mem.read(self.ic + (i + 1)));
+                if !self.permitted(self.mem.peek(self.ic)) {
+                    return None;
                 }
                 // Incrementing the program counter here is fine because the instruction
                 // is ecexuted afterwords, this means we don't mess with our jump addresses.
+                self.steps += 1;
+                self.observers.begin(self.steps, self.ic, self.relative_base);
                 self.ic = self.ic + instruction.len;
                 Some(instruction)
             },
@@ -192,7 +430,34 @@ impl IntCode {
         IntCode {
             mem,
             ic: 0,
+            last_ic: 0,
             relative_base: 0,
+            steps: 0,
+            observers: Observers::default(),
+            replay: None,
+            journal: None,
+            memory_limit: None,
+            fault: None,
+            policy: Policy::Lenient,
+        }
+    }
+
+    // Runs until the program produces a value, wants input we don't have, or halts.
+    // A fault also ends in `Halted`, with the fault left in `self.fault`.
+    fn resume(&mut self, input: &mut Vec<i64>) -> Status {
+        loop {
+            if input.is_empty() && Instruction::new(self.mem.peek(self.ic)).op == OpCode::Input {
+                return Status::NeedInput;
+            }
+
+            match self.next() {
+                Some(i) => {
+                    if let Some(value) = self.execute(i, input) {
+                        return Status::Output(value);
+                    }
+                },
+                None => return Status::Halted,
+            }
         }
     }
 
@@ -205,128 +470,243 @@ impl IntCode {
             };
         }
 
+        if let Some(fault) = &self.fault {
+            println!("{}", fault);
+            exit(1);
+        }
+
         output
     }
 
-    fn execute(&mut self, i: Instruction, input: &mut Vec<i64>) -> Option<i64> {
-        let mut output = None;
+    // Like `run_program`, but reports bad opcodes, missing input and runaway
+    // programs as a `Fault` rather than exiting or panicking.
+    fn try_run(&mut self, input: &mut Vec<i64>, limit: usize) -> Result<Vec<i64>, Fault> {
+        let mut output = Vec::<i64>::new();
+
+        while self.try_step(input, &mut output, limit)? {}
+
+        Ok(output)
+    }
+
+    // A single instruction of `try_run`, false once the program halts.
+    fn try_step(&mut self, input: &mut Vec<i64>, output: &mut Vec<i64>, limit: usize) -> Result<bool, Fault> {
+        if let Some(fault) = self.fault.take() {
+            return Err(fault);
+        }
+
+        let value = self.mem.peek(self.ic);
+
+        match Instruction::new(value).op {
+            OpCode::Unknown => return Err(Fault::InvalidOpCode { value, addr: self.ic }),
+            OpCode::Input if input.is_empty() => return Err(Fault::NeedInput { addr: self.ic }),
+            OpCode::Halt => (),
+            _ if self.steps >= limit => return Err(Fault::StepLimit { steps: self.steps }),
+            _ => (),
+        }
+
+        match self.next() {
+            Some(i) => {
+                output.extend(self.execute(i, input));
+                Ok(true)
+            },
+            None => self.fault.take().map_or(Ok(false), Err),
+        }
+    }
 
+    // Handlers return None when the instruction faulted, the fault is left in
+    // `self.fault` and stops the machine before the next fetch.
+    fn execute(&mut self, i: Instruction, input: &mut Vec<i64>) -> Option<i64> {
         match i.op {
             OpCode::Add => self.add(i),
             OpCode::Mul => self.mul(i),
             OpCode::Input => self.input(i, input),
-            OpCode::Output => output = self.output(i),
+            OpCode::Output => return self.output(i),
             OpCode::JumpIfFalse => self.jump_if_false(i),
             OpCode::JumpIfTrue => self.jump_if_true(i),
             OpCode::Equals => self.equal(i),
             OpCode::LessThan => self.less_than(i),
             OpCode::RelativeBase => self.relative_inc(i),
-            _ => (),
-        }
+            _ => None,
+        };
 
-        output
+        None
     }
 
-    fn input(&self, i: Instruction, inputs: &mut Vec<i64>) {
-        let op1 = match &i.modes[0] {
-            ParameterMode::Relative => {
-                let pos = self.relative_base as i64 + i.args[0].unwrap();
-                pos as usize
-            },
-            _ => i.args[0].unwrap() as usize,
-        };
+    fn input(&mut self, i: Instruction, inputs: &mut Vec<i64>) -> Option<()> {
+        let op1 = self.address(i.args[0].unwrap(), &i.modes[0])?;
+        let value = inputs.remove(0);
+
+        self.observers.input(value);
 
-        self.mem.write(op1, inputs.remove(0));
+        self.store(op1, value)
     }
 
-    fn output(&self, i: Instruction) -> Option<i64> {
-        let op1 = self.value(i.args[0].unwrap(), &i.modes[0]);
+    fn output(&mut self, i: Instruction) -> Option<i64> {
+        let op1 = self.value(i.args[0].unwrap(), &i.modes[0])?;
+
+        self.observers.output(op1);
 
         Some(op1)
     }
 
-    fn add(&self, i: Instruction) {
-        let op1 = self.value(i.args[0].unwrap(), &i.modes[0]);
-        let op2 = self.value(i.args[1].unwrap(), &i.modes[1]);
-        let op3 = match i.modes[2] {
-            ParameterMode::Relative => i.args[2].unwrap() + self.relative_base as i64,
-            _ => i.args[2].unwrap(),
-        };
+    fn add(&mut self, i: Instruction) -> Option<()> {
+        let op1 = self.value(i.args[0].unwrap(), &i.modes[0])?;
+        let op2 = self.value(i.args[1].unwrap(), &i.modes[1])?;
+        let op3 = self.address(i.args[2].unwrap(), &i.modes[2])?;
+        let result = self.arithmetic(op1.checked_add(op2))?;
 
-        self.mem.write(op3 as usize, op1 + op2);
+        self.store(op3, result)
     }
 
-    fn mul(&self, i: Instruction) {
-        let op1 = self.value(i.args[0].unwrap(), &i.modes[0]);
-        let op2 = self.value(i.args[1].unwrap(), &i.modes[1]);
-        let op3 = match i.modes[2] {
-            ParameterMode::Relative => i.args[2].unwrap() + self.relative_base as i64,
-            _ => i.args[2].unwrap(),
-        };
+    fn mul(&mut self, i: Instruction) -> Option<()> {
+        let op1 = self.value(i.args[0].unwrap(), &i.modes[0])?;
+        let op2 = self.value(i.args[1].unwrap(), &i.modes[1])?;
+        let op3 = self.address(i.args[2].unwrap(), &i.modes[2])?;
+        let result = self.arithmetic(op1.checked_mul(op2))?;
 
-        self.mem.write(op3 as usize, op1 * op2);
+        self.store(op3, result)
     }
 
-    fn jump_if_true(&mut self, i: Instruction) {
-        let op1 = self.value(i.args[0].unwrap(), &i.modes[0]);
-        let op2 = self.value(i.args[1].unwrap(), &i.modes[1]);
+    fn jump_if_true(&mut self, i: Instruction) -> Option<()> {
+        let op1 = self.value(i.args[0].unwrap(), &i.modes[0])?;
+        let op2 = self.value(i.args[1].unwrap(), &i.modes[1])?;
 
-        if op1 != 0 { self.ic = op2 as usize; }
+        if op1 != 0 { self.ic = self.target(op2)?; }
+
+        Some(())
     }
 
-    fn jump_if_false(&mut self, i: Instruction) {
-        let op1 = self.value(i.args[0].unwrap(), &i.modes[0]);
-        let op2 = self.value(i.args[1].unwrap(), &i.modes[1]);
+    fn jump_if_false(&mut self, i: Instruction) -> Option<()> {
+        let op1 = self.value(i.args[0].unwrap(), &i.modes[0])?;
+        let op2 = self.value(i.args[1].unwrap(), &i.modes[1])?;
+
+        if op1 == 0 { self.ic = self.target(op2)?; }
 
-        if op1 == 0 { self.ic = op2 as usize; }
+        Some(())
     }
 
-    fn less_than(&self, i: Instruction) {
-        let op1 = self.value(i.args[0].unwrap(), &i.modes[0]);
-        let op2 = self.value(i.args[1].unwrap(), &i.modes[1]);
-        let op3 = match i.modes[2] {
-            ParameterMode::Relative => i.args[2].unwrap() + self.relative_base as i64,
-            _ => i.args[2].unwrap(),
-        };
+    fn less_than(&mut self, i: Instruction) -> Option<()> {
+        let op1 = self.value(i.args[0].unwrap(), &i.modes[0])?;
+        let op2 = self.value(i.args[1].unwrap(), &i.modes[1])?;
+        let op3 = self.address(i.args[2].unwrap(), &i.modes[2])?;
 
         if op1 < op2 {
-            self.mem.write(op3 as usize, 1);
+            self.store(op3, 1)
         } else {
-            self.mem.write(op3 as usize, 0);
+            self.store(op3, 0)
         }
     }
 
-    fn equal(&self, i: Instruction) {
-        let op1 = self.value(i.args[0].unwrap(), &i.modes[0]);
-        let op2 = self.value(i.args[1].unwrap(), &i.modes[1]);
-        let op3 = match i.modes[2] {
-            ParameterMode::Relative => i.args[2].unwrap() + self.relative_base as i64,
-            _ => i.args[2].unwrap(),
-        };
+    fn equal(&mut self, i: Instruction) -> Option<()> {
+        let op1 = self.value(i.args[0].unwrap(), &i.modes[0])?;
+        let op2 = self.value(i.args[1].unwrap(), &i.modes[1])?;
+        let op3 = self.address(i.args[2].unwrap(), &i.modes[2])?;
 
         if op1 == op2 {
-            self.mem.write(op3 as usize, 1);
+            self.store(op3, 1)
         } else {
-            self.mem.write(op3 as usize, 0);
+            self.store(op3, 0)
         }
     }
 
-    fn relative_inc(&mut self, i: Instruction) {
-        let op1 = self.value(i.args[0].unwrap(), &i.modes[0]);
-        let new_base = self.relative_base as i64 + op1;
+    fn relative_inc(&mut self, i: Instruction) -> Option<()> {
+        let op1 = self.value(i.args[0].unwrap(), &i.modes[0])?;
+        let new_base = self.arithmetic((self.relative_base as i64).checked_add(op1))?;
 
+        // A negative base is fine as long as the addresses built from it aren't,
+        // it survives the round trip through usize.
         self.relative_base = new_base as usize;
+
+        self.observers.relative_base(self.relative_base);
+
+        Some(())
+    }
+
+    // Applies the decoding policy to the label about to run.
+    fn permitted(&mut self, label: i64) -> bool {
+        let hook = match self.policy {
+            Policy::Lenient => return true,
+            Policy::Warn(hook) => Some(hook),
+            Policy::Strict => None,
+        };
+
+        match (Instruction::illegal(label, self.ic), hook) {
+            (Some(fault), Some(hook)) => hook(&fault),
+            (Some(fault), None) => return self.fail::<()>(fault).is_some(),
+            (None, _) => (),
+        }
+
+        true
+    }
+
+    // The start of the instruction that raised `fault`. Faults found before
+    // fetching leave `ic` on it, the rest happen after `ic` has moved on.
+    fn fault_ic(&self, fault: &Fault) -> usize {
+        match fault {
+            Fault::Overflow | Fault::BadAddress { .. } | Fault::MemoryLimit { .. } => self.last_ic,
+            _ => self.ic,
+        }
+    }
+
+    fn fail<T>(&mut self, fault: Fault) -> Option<T> {
+        self.fault = Some(fault);
+        None
+    }
+
+    fn arithmetic(&mut self, result: Option<i64>) -> Option<i64> {
+        match result {
+            Some(v) => Some(v),
+            None => self.fail(Fault::Overflow),
+        }
+    }
+
+    fn target(&mut self, addr: i64) -> Option<usize> {
+        if addr < 0 {
+            return self.fail(Fault::BadAddress { addr });
+        }
+
+        Some(addr as usize)
+    }
+
+    // Where a position or relative operand points, immediate operands used as a
+    // destination are treated as positions.
+    fn address(&mut self, op: i64, pm: &ParameterMode) -> Option<usize> {
+        let addr = match pm {
+            ParameterMode::Relative => self.arithmetic(op.checked_add(self.relative_base as i64))?,
+            _ => op,
+        };
+
+        self.target(addr)
     }
 
-    fn value(&self, op: i64, pm: &ParameterMode) -> i64 {
+    fn value(&mut self, op: i64, pm: &ParameterMode) -> Option<i64> {
         match pm {
-            ParameterMode::Immediate => op,
-            ParameterMode::Position => self.mem.read(op as usize),
-            ParameterMode::Relative => {
-                let position = op + self.relative_base as i64;
-                self.mem.read(position as usize)
-            }
+            ParameterMode::Immediate => Some(op),
+            _ => {
+                let addr = self.address(op, pm)?;
+                Some(self.load(addr))
+            },
+        }
+    }
+
+    fn load(&mut self, addr: usize) -> i64 {
+        let value = self.mem.read(addr);
+
+        self.observers.read(addr, value);
+        value
+    }
+
+    fn store(&mut self, addr: usize, value: i64) -> Option<()> {
+        if self.memory_limit.is_some_and(|limit| addr >= limit) {
+            return self.fail(Fault::MemoryLimit { addr });
         }
+        let old = self.mem.peek(addr);
+
+        self.observers.write(addr, old, value);
+
+        self.mem.write(addr, value);
+
+        Some(())
     }
 }
 
@@ -344,33 +724,57 @@ impl Amplifier {
     }
 
     fn run(&mut self, input: i64) -> Option<i64> {
-        let mut result = None;
         let mut input = if self.cpu.ic == 0 {
             vec![self.phase, input]
         } else {
             vec![input]
         };
 
-        while let Some(i) = self.cpu.next() {
-            match i.op {
-                OpCode::Output => {
-                    result = self.cpu.execute(i, &mut input);
-                    break;
-                },
-                _ => {
-                    self.cpu.execute(i, &mut input);
-                }
-            }
+        match self.cpu.resume(&mut input) {
+            Status::Output(value) => Some(value),
+            _ => None,
         }
-
-        result
     }
 }
 
 fn main() {
     let mut source = File::open(Path::new(&args().next_back().unwrap())).unwrap();
 
-    day9(&mut source);
+    match args().nth(1).as_deref() {
+        Some("disasm") => disassemble(&mut source),
+        Some("decompile") => decompile(&mut source),
+        Some("profile") => profile(&mut source),
+        Some("trace") => trace(&mut source),
+        Some("backtrace") => backtrace(&mut source),
+        Some("coverage") => coverage(&mut source),
+        Some("optimize") => peephole(&mut source),
+        Some("compile") => compile_source(&mut source),
+        Some("debug-source") => debug_source(&mut source),
+        Some("link") => link_objects(),
+        Some("jit") => jit(&mut source),
+        Some("expect") => expect_script(&mut source),
+        Some("tui") => visualize(&mut source, false),
+        Some("tui-amp") => visualize(&mut source, true),
+        Some("coverage-amp") => amplifier_coverage(&mut source),
+        Some("inspect") => inspect(&mut source),
+        Some("record") => record(&mut source),
+        Some("replay") => verify_replay(&mut source),
+        Some("debug") => debug(&mut source),
+        Some("aot") => compile(&mut source),
+        Some("aot-check") => aot_check(&mut source),
+        Some("robot") => paint_hull(&mut source),
+        Some("mmio") => mmio(&mut source),
+        Some("maze") => maze(&mut source),
+        Some("screen") => screen(&mut source),
+        Some("pixels") => pixels(&mut source),
+        Some("solve") => solve(&mut source),
+        Some("solve-input") => solve_input(&mut source),
+        Some("test") => run_tests(),
+        Some("lint") => lint(&mut source),
+        Some("fuzz") => fuzz(&mut source),
+        Some("lockstep") => lockstep(&mut source),
+        _ => day9(&mut source),
+    }
 
     //day7(&mut source);
 
@@ -394,13 +798,656 @@ fn main() {
     //println!("{}", intcode.mem.read(0));
 }
 
-fn day9(source: &mut File) {
+fn disassemble(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    println!("{}", disasm::listing(&buf));
+}
+
+fn decompile(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    println!("{}", decompile::decompile(&buf));
+}
+
+fn compile(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let compiled = aot::compile(&buf);
+
+    eprintln!("{} native instructions, {} interpreted", compiled.native, compiled.interpreted);
+    print!("{}", compiled.source);
+}
+
+fn aot_check(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    match aot::differential(&buf, &cli_inputs()) {
+        Ok(output) => println!("Compiled output matches: {:?}", output),
+        Err(e) => {
+            println!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+fn profile(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf);
+
+    let mut intcode = IntCode::new(mem);
+    let mut input = cli_inputs();
+
+    let profile = intcode.observe(Profile::new());
+    dbg!(intcode.run_program(&mut input));
+
+    println!("{}", profile.borrow().report(20));
+}
+
+// Dumps a range of memory after a run, annotated with what the program did to
+// it, followed by every cell that changed. The first two command line values
+// are the range, the rest are input.
+fn inspect(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf.clone());
+
+    let mut intcode = IntCode::new(mem);
+    let mut input = cli_inputs();
+
+    if input.len() < 2 || input[0] < 0 || input[1] < 0 {
+        eprintln!("usage: inspect <from> <to> <input>... <program>");
+        exit(1);
+    }
+
+    let range = input.remove(0) as usize..input.remove(0) as usize;
+
+    let profile = intcode.observe(Profile::new());
+    intcode.run_program(&mut input);
+
+    let after = intcode.mem.snapshot();
+    let inspector = inspect::Inspector::new(&after).profile(&profile.borrow());
+
+    print!("{}", inspector.dump(range));
+    println!("\nchanged:");
+    print!("{}", inspect::render_diff(&inspect::diff(&buf, &after)));
+}
+
+fn trace(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf);
+
+    let mut intcode = IntCode::new(mem);
+    let trace = intcode.observe(observer::Trace::default());
+
+    intcode.run_program(&mut cli_inputs());
+
+    for line in trace.borrow().lines.iter() {
+        println!("{}", line);
+    }
+}
+
+fn backtrace(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf);
+
+    let mut intcode = IntCode::new(mem);
+    let calls = intcode.observe(callstack::CallStack::new());
+
+    match intcode.try_run(&mut cli_inputs(), 1_000_000) {
+        Ok(output) => println!("{:?}", output),
+        Err(fault) => {
+            println!("{}", fault);
+            print!("{}", calls.borrow().backtrace(intcode.fault_ic(&fault)));
+        },
+    }
+}
+
+fn coverage(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf.clone());
+
+    let mut intcode = IntCode::new(mem);
+    let coverage = intcode.observe(coverage::Coverage::new());
+
+    intcode.run_program(&mut cli_inputs());
+
+    let coverage = coverage.borrow();
+
+    println!("{}\n", coverage.annotate(&buf));
+    print!("{}", coverage.summary(&buf).report());
+}
+
+// Coverage over every phase permutation of both amplifier setups from day 7.
+fn amplifier_coverage(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mut total = coverage::Coverage::new();
+
+    for phases in (0..5).permutations(5).chain((5..10).permutations(5)) {
+        let mut handles = Vec::new();
+        let mut amps = phases.iter()
+            .map(|phase| {
+                let mut intcode = IntCode::new(Memory::new(buf.clone()));
+
+                handles.push(intcode.observe(coverage::Coverage::new()));
+                Amplifier::new(*phase, intcode)
+            })
+            .collect::<Vec<Amplifier>>();
+
+        let mut value = 0;
+
+        while !amps.is_empty() {
+            amps.retain_mut(|amp| match amp.run(value) {
+                Some(v) => {
+                    value = v;
+                    true
+                },
+                None => false,
+            });
+        }
+
+        for coverage in handles.iter() {
+            total.merge(&coverage.borrow());
+        }
+    }
+
+    println!("{}\n", total.annotate(&buf));
+    print!("{}", total.summary(&buf).report());
+}
+
+// Lists what the optimizer changed, checks the result against the original
+// on the command line input and prints the optimized image.
+fn peephole(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let optimized = optimize::optimize(&buf);
+
+    for rewrite in optimized.rewrites.iter() {
+        eprintln!("{}", rewrite);
+    }
+
+    match optimize::verify(&buf, &optimized, &[cli_inputs()], 10_000_000) {
+        Ok((before, after)) => eprintln!("steps: {} -> {}", before, after),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        },
+    }
+
+    println!("{}", optimized.image.iter().join(","));
+}
+
+fn replay_path() -> PathBuf {
+    let mut path = args().next_back().unwrap();
+    path.push_str(".replay");
+
+    PathBuf::from(path)
+}
+
+fn record(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf);
+
+    let mut intcode = IntCode::new(mem);
+    let mut input = cli_inputs();
+
+    let replay = intcode.record();
+    dbg!(intcode.run_program(&mut input));
+
+    replay.borrow().save(&replay_path()).unwrap();
+}
+
+fn verify_replay(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let log = Replay::load(&replay_path()).unwrap();
+
+    match replay::verify(&buf, &log, 10_000_000) {
+        Ok(()) => println!("Replay matches: {} events", log.events.len()),
+        Err(divergence) => {
+            println!("Replay diverged at {}", divergence);
+            exit(1);
+        }
+    }
+}
+
+fn debug(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf);
+
+    let mut debugger = debugger::Debugger::new(IntCode::new(mem), cli_inputs());
+    debugger.repl();
+}
+
+fn read_source(source: &mut File) -> compiler::Compiled {
+    let mut text = String::new();
+    source.read_to_string(&mut text).unwrap();
+
+    compiler::compile(&text).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    })
+}
+
+// Compiles a source file and prints the image, with the disassembly annotated
+// with source lines on stderr.
+fn compile_source(source: &mut File) {
+    let compiled = read_source(source);
+    let mut line = None;
+
+    for (addr, instruction) in disasm::disassemble(&compiled.image) {
+        if let Some(name) = compiled.map.functions.get(&addr) {
+            eprintln!("{}:", name);
+        }
+
+        let at = compiled.map.line(addr);
+        if at.is_some() && at != line {
+            eprintln!("  ; {}", compiled.map.describe(addr).unwrap());
+            line = at;
+        }
+
+        if at.is_some() {
+            eprintln!("{:>6}: {}", addr, instruction);
+        }
+    }
+
+    println!("{}", compiled.image.iter().join(","));
+}
+
+fn debug_source(source: &mut File) {
+    let compiled = read_source(source);
+
+    let mem = Memory::new(compiled.image);
+
+    let mut debugger = debugger::Debugger::new(IntCode::new(mem), cli_inputs());
+    debugger.source = Some(compiled.map);
+    debugger.repl();
+}
+
+// Links every object file on the command line, in order, and prints the
+// image in the format `load_program` reads.
+fn link_objects() {
+    let mut objects = Vec::new();
+
+    for path in args().skip(2) {
+        objects.extend(link::Object::load(Path::new(&path)).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(1);
+        }));
+    }
+
+    match link::link(&objects) {
+        Ok(linked) => {
+            for (name, base) in linked.bases.iter() {
+                eprintln!("{:>6}: {}", base, name);
+            }
+            println!("{}", linked.image.iter().join(","));
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        },
+    }
+}
+
+// Drives an ASCII program with the script given before the program path and
+// prints what it said.
+fn expect_script(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    if args().len() < 4 {
+        eprintln!("usage: expect <script> <program>");
+        exit(1);
+    }
+
+    let path = args().nth(2).unwrap();
+    let script = expect::load(Path::new(&path)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    let run = script.run(buf);
+
+    print!("{}", run.transcript);
+    for value in run.values.iter() {
+        println!("{}", value);
+    }
+    eprintln!("{} steps", run.steps);
+
+    if let Err(e) = run.result {
+        eprintln!("{}: {}", path, e);
+        exit(1);
+    }
+}
+
+#[cfg(feature = "jit")]
+fn jit(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mut jit = jit::Jit::new(buf, 100);
+
+    match jit.run(&mut cli_inputs(), usize::MAX) {
+        Ok(output) => println!("{:?}", output),
+        Err(fault) => println!("{}", fault),
+    }
+
+    eprintln!("{:?}", jit.stats);
+}
+
+#[cfg(not(feature = "jit"))]
+fn jit(_source: &mut File) {
+    eprintln!("built without the jit feature");
+    exit(1);
+}
+
+// Full screen view of a running machine, or with `feedback` of one amplifier
+// per phase given on the command line wired in a loop.
+#[cfg(feature = "tui")]
+fn visualize(source: &mut File, feedback: bool) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let machines = match feedback {
+        false => vec![tui::Machine::new("main", buf, cli_inputs())],
+        true => cli_inputs().iter().enumerate()
+            .map(|(i, phase)| {
+                let input = if i == 0 { vec![*phase, 0] } else { vec![*phase] };
+                // A to Z, then plain numbers.
+                let name = match i {
+                    0..=25 => ((b'A' + i as u8) as char).to_string(),
+                    _ => i.to_string(),
+                };
+                tui::Machine::new(&name, buf.clone(), input)
+            })
+            .collect(),
+    };
+
+    if machines.is_empty() {
+        eprintln!("usage: tui-amp <phase>... <program>");
+        exit(1);
+    }
+
+    let mut visualizer = tui::Visualizer::new(machines, feedback);
+
+    if let Err(e) = tui::show(&mut visualizer) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+
+    for machine in visualizer.machines.iter() {
+        println!("{}: {:?}", machine.name, machine.output);
+    }
+}
+
+#[cfg(not(feature = "tui"))]
+fn visualize(_source: &mut File, _feedback: bool) {
+    eprintln!("built without the tui feature");
+    exit(1);
+}
+
+// Hull painting robot, an optional command line value is the color of the starting panel.
+fn paint_hull(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf);
+
+    let mut intcode = IntCode::new(mem);
+    let mut robot = robot::Robot::new();
+
+    if let Some(color) = cli_inputs().first() {
+        robot.panels.insert(robot.pos, *color);
+    }
+
+    robot.run(&mut intcode);
+
+    println!("Painted: {}", robot.painted.len());
+    println!("{}", robot.screen().render());
+}
+
+// Maps the area around a repair droid, then reports the distance to the oxygen
+// system and how long it takes to fill the area with oxygen.
+fn maze(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf);
+
+    let maze = maze::Maze::explore(IntCode::new(mem));
+
+    println!("{}", maze.screen().render());
+
+    if let Some(oxygen) = maze.oxygen {
+        println!("Shortest path: {}", maze.shortest_path((0, 0), oxygen).unwrap());
+        println!("Fill time: {}", maze.fill_time(oxygen));
+    }
+}
+
+// Runs a program with the standard peripherals mapped: a console at 1000, a
+// clock at 1001, a random source at 1002 and a 40x6 framebuffer from 2000.
+// Command line values are queued on the console.
+fn mmio(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mut mem = Memory::new(buf);
+    let console = mem.map(1000..1001, device::Console::default()).unwrap();
+    mem.map(1001..1002, device::Clock::default());
+    mem.map(1002..1003, device::Random::new(2019));
+    let framebuffer = mem.map(2000..2240, device::Framebuffer::new(40, 6).unwrap()).unwrap();
+
+    console.borrow_mut().input.extend(cli_inputs());
+
+    let output = IntCode::new(mem).run_program(&mut vec![]);
+
+    println!("{}", console.borrow().text());
+    println!("{}", framebuffer.borrow().screen(screen::Palette::hull()).render());
+    if !output.is_empty() {
+        println!("{:?}", output);
+    }
+}
+
+// Draws triple output as an arcade screen and keeps a PPM copy next to the program.
+fn screen(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf);
+
+    let mut intcode = IntCode::new(mem);
+    let mut screen = screen::Screen::new(screen::Palette::arcade());
+    let mut path = args().next_back().unwrap();
+
+    screen.update(&intcode.run_program(&mut cli_inputs()));
+    screen.draw(&mut std::io::stdout()).unwrap();
+    println!("Blocks: {}", screen.count(2));
+
+    path.push_str(".ppm");
+    screen.save_ppm(Path::new(&path), 4).unwrap();
+}
+
+// Same as `screen` for programs that stream one tile per output, the first
+// command line value is the row width.
+fn pixels(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf);
+
+    let mut intcode = IntCode::new(mem);
+    let mut screen = screen::Screen::new(screen::Palette::hull());
+    let mut input = cli_inputs();
+
+    if input.first().is_none_or(|w| *w < 1) {
+        eprintln!("usage: pixels <width> <input>... <program>");
+        exit(1);
+    }
+
+    let width = input.remove(0) as usize;
+    let mut path = args().next_back().unwrap();
+
+    screen.pixels(width, &intcode.run_program(&mut input));
+    screen.draw(&mut std::io::stdout()).unwrap();
+
+    path.push_str(".ppm");
+    screen.save_ppm(Path::new(&path), 4).unwrap();
+}
+
+// Finds the noun and verb for day2 without trying all 10,000 pairs.
+fn solve(source: &mut File) {
     let mut buf = Vec::<i64>::new();
     load_program(&mut buf, source);
 
-    let mem = Memory {
-        bucket: RefCell::new(buf),
+    let target = match cli_inputs().first() {
+        Some(target) => *target,
+        None => {
+            eprintln!("usage: solve <target> <program>");
+            exit(1);
+        },
     };
+    let problem = symbolic::Symbolic::new(&buf)
+        .cell(1, "noun", 0, 99)
+        .cell(2, "verb", 0, 99);
+
+    for solution in problem.solve(symbolic::Goal::Memory(0), target) {
+        println!("x: {}, y: {}", solution["noun"], solution["verb"]);
+    }
+}
+
+// Finds a first input making the first output equal the target, any further
+// command line values are passed as concrete inputs after it.
+fn solve_input(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let inputs = cli_inputs();
+
+    if inputs.is_empty() {
+        eprintln!("usage: solve-input <target> <input>... <program>");
+        exit(1);
+    }
+
+    let problem = inputs[1..].iter().fold(
+        symbolic::Symbolic::new(&buf).input("x", -1_000_000, 1_000_000),
+        |problem, v| problem.constant_input(*v),
+    );
+
+    for solution in problem.solve(symbolic::Goal::Output(0), inputs[0]) {
+        println!("x: {}", solution["x"]);
+    }
+}
+
+// Runs the program reporting every label strict decoding would reject, without
+// stopping on them.
+fn lint(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf);
+
+    let mut intcode = IntCode::new(mem);
+
+    intcode.policy = Policy::Warn(|fault| eprintln!("warning: {}", fault));
+    println!("{:?}", intcode.run_program(&mut cli_inputs()));
+}
+
+// Runs a case file, or a directory of them, given as the last argument.
+fn run_tests() {
+    let path = args().next_back().unwrap();
+    let cases = testcase::load(Path::new(&path)).unwrap_or_else(|e| {
+        println!("{}", e);
+        exit(1);
+    });
+    let failures = testcase::run_all(&cases);
+
+    for (name, reason) in failures.iter() {
+        println!("FAIL {}: {}", name, reason);
+    }
+    println!("{} passed, {} failed", cases.len() - failures.len(), failures.len());
+
+    if !failures.is_empty() {
+        exit(1);
+    }
+}
+
+// Mutates the program looking for inputs that crash the VM, an optional command
+// line value is the number of runs.
+fn fuzz(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let iterations = cli_inputs().first().map_or(100_000, |n| *n as usize);
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(1, |d| d.as_secs());
+    let mut rng = fuzz::Rng::new(seed);
+
+    match fuzz::fuzz(&mut rng, &[buf], iterations, fuzz::Limits::default()) {
+        Some(failure) => {
+            println!("{}", failure.reason);
+            println!("program {}", failure.program.iter().join(","));
+            println!("input {}", failure.input.iter().join(","));
+            exit(1);
+        },
+        None => println!("{} runs, no failures (seed {})", iterations, seed),
+    }
+}
+
+// Checks the pre-decoded engine against the interpreter on this program.
+fn lockstep(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf.clone());
+
+    let mut intcode = IntCode::new(mem);
+    let mut predecoded = predecode::Predecoded::new(&buf);
+
+    match lockstep::lockstep(&mut intcode, &mut predecoded, &cli_inputs(), usize::MAX) {
+        Ok(steps) => println!("{} steps, no divergence", steps),
+        Err(mismatch) => {
+            println!("{}", mismatch);
+            exit(1);
+        },
+    }
+}
+
+// Everything between the command and the trailing program path is taken as input.
+fn cli_inputs() -> Vec<i64> {
+    let args = args().collect::<Vec<String>>();
+
+    args[2..args.len() - 1].iter()
+        .map(|a| a.parse::<i64>().unwrap())
+        .collect()
+}
+
+fn day9(source: &mut File) {
+    let mut buf = Vec::<i64>::new();
+    load_program(&mut buf, source);
+
+    let mem = Memory::new(buf);
 
     let mut intcode = IntCode::new(mem);
     let mut input: Vec<i64> = vec![2];
@@ -438,9 +1485,7 @@ fn feedback(seq: &Vec<i64>, mem: &Vec<i64>) -> i64 {
     let mut amps = Vec::<Amplifier>::new();
 
     for i in seq.iter() {
-        let memory = Memory {
-            bucket: RefCell::new(mem.clone()),
-        };
+        let memory = Memory::new(mem.clone());
         let intcode = IntCode::new(memory);
         let amp = Amplifier::new(*i, intcode);
 
@@ -467,9 +1512,7 @@ fn amplifier_sequence(seq: &Vec<i64>, mem: &Vec<i64>) -> i64 {
     let mut output = 0;
 
     for x in 0..5 {
-        let memory = Memory {
-            bucket: RefCell::new(mem.clone()),
-        };
+        let memory = Memory::new(mem.clone());
         let mut intcode = IntCode::new(memory);
         let mut args: Vec<i64> = Vec::new();
 
@@ -487,9 +1530,7 @@ fn day5(source: &mut File, mod_id: i64) {
     let mut buf = Vec::<i64>::new();
     load_program(&mut buf, source);
 
-    let memory = Memory {
-        bucket: RefCell::new(buf),
-    };
+    let memory = Memory::new(buf);
     let mut intcode = IntCode::new(memory);
     let mut inputs = vec![mod_id];
 
@@ -502,9 +1543,7 @@ fn day2(source: &mut File) {
             let mut buf = Vec::<i64>::new();
             load_program(&mut buf, source);
 
-            let memory = Memory {
-                bucket: RefCell::new(buf),
-            };
+            let memory = Memory::new(buf);
             let mut intcode = IntCode::new(memory);
             let mut inputs = vec![];
 
@@ -592,9 +1631,7 @@ mod tests {
 
     #[test]
     fn execution() {
-        let memory = Memory {
-            bucket: RefCell::new(vec![3,9,8,9,10,9,4,9,99,-1,8]),
-        };
+        let memory = Memory::new(vec![3,9,8,9,10,9,4,9,99,-1,8]);
         let mut intcode = IntCode::new(memory);
         let mut args = vec![7];
 
@@ -641,9 +1678,7 @@ mod tests {
     #[test]
     fn boost_all() {
         let buf = vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
-        let memory = Memory {
-            bucket: RefCell::new(buf.clone()),
-        };
+        let memory = Memory::new(buf.clone());
         let mut intcode = IntCode::new(memory);
         let mut input = vec![1];
         let output = intcode.run_program(&mut input);
@@ -654,9 +1689,7 @@ mod tests {
     #[test]
     fn boost_long_number() {
         let buf = vec![1102,34915192,34915192,7,4,7,99,0];
-        let memory = Memory {
-            bucket: RefCell::new(buf.clone()),
-        };
+        let memory = Memory::new(buf.clone());
         let mut intcode = IntCode::new(memory);
         let mut input = vec![1];
         let output = intcode.run_program(&mut input);
@@ -667,9 +1700,7 @@ mod tests {
     #[test]
     fn boost_middle_number() {
         let buf = vec![104,1125899906842624,99];
-        let memory = Memory {
-            bucket: RefCell::new(buf.clone()),
-        };
+        let memory = Memory::new(buf.clone());
         let mut intcode = IntCode::new(memory);
         let mut input = vec![1];
         let output = intcode.run_program(&mut input);
@@ -678,9 +1709,32 @@ mod tests {
     }
 
 
+    #[test]
+    fn decoding_policy() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+        let run = |buf: Vec<i64>, policy| {
+            let mut intcode = IntCode::new(Memory::new(buf));
+            intcode.policy = policy;
+            (intcode.try_run(&mut vec![], 100), intcode.mem.read(7))
+        };
+        let immediate_dst = vec![11101,5,6,7,99,2,3];
+
+        assert_eq!(run(immediate_dst.clone(), Policy::Lenient), (Ok(vec![]), 11));
+        assert_eq!(run(immediate_dst.clone(), Policy::Strict), (Err(Fault::ImmediateWrite { addr: 0 }), 0));
+        assert_eq!(run(vec![1301,5,6,7,99], Policy::Strict).0, Err(Fault::IllegalMode { addr: 0, mode: 3 }));
+        // modes past the instruction's arity don't matter
+        assert_eq!(run(vec![30104,0,99], Policy::Strict).0, Ok(vec![0]));
+
+        let warned = run(immediate_dst, Policy::Warn(|_| { WARNINGS.fetch_add(1, Ordering::SeqCst); }));
+
+        assert_eq!(warned, (Ok(vec![]), 11));
+        assert_eq!(WARNINGS.load(Ordering::SeqCst), 1);
+    }
+
     fn memory() -> Memory {
-        Memory {
-            bucket: RefCell::new(vec![1, 10, 11, 12, 2, 12, 10, 12, 101, 0, 1, 8, 99, 10, 3, 0, 0]),
-        }
+        Memory::new(vec![1, 10, 11, 12, 2, 12, 10, 12, 101, 0, 1, 8, 99, 10, 3, 0, 0])
     }
 }
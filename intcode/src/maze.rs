@@ -0,0 +1,155 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::screen::{Palette, Screen};
+use super::{IntCode, Status};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tile {
+    Wall,
+    Open,
+    Oxygen,
+}
+
+impl Tile {
+    fn from_status(status: i64) -> Option<Tile> {
+        match status {
+            0 => Some(Tile::Wall),
+            1 => Some(Tile::Open),
+            2 => Some(Tile::Oxygen),
+            _ => None,
+        }
+    }
+}
+
+// Movement commands understood by the droid: north, south, west and east.
+const MOVES: [(i64, (i64, i64)); 4] = [(1, (0, -1)), (2, (0, 1)), (3, (-1, 0)), (4, (1, 0))];
+
+#[derive(Debug, Default)]
+pub struct Maze {
+    pub map: HashMap<(i64, i64), Tile>,
+    pub oxygen: Option<(i64, i64)>,
+}
+
+impl Maze {
+    // Walks every reachable cell depth first. Instead of sending the droid back
+    // after each dead end, the machine is snapshotted at every open cell and the
+    // next probe starts from that snapshot.
+    pub fn explore(cpu: IntCode) -> Maze {
+        let mut maze = Maze::default();
+        let mut stack = vec![((0, 0), cpu)];
+
+        maze.map.insert((0, 0), Tile::Open);
+
+        while let Some(((x, y), cpu)) = stack.pop() {
+            for (command, (dx, dy)) in MOVES.iter() {
+                let next = (x + dx, y + dy);
+
+                if maze.map.contains_key(&next) {
+                    continue;
+                }
+
+                let mut probe = cpu.clone();
+                let tile = match probe.resume(&mut vec![*command]) {
+                    Status::Output(status) => Tile::from_status(status),
+                    _ => None,
+                };
+
+                match tile {
+                    Some(Tile::Wall) => {
+                        maze.map.insert(next, Tile::Wall);
+                    },
+                    Some(tile) => {
+                        if tile == Tile::Oxygen {
+                            maze.oxygen = Some(next);
+                        }
+                        maze.map.insert(next, tile);
+                        stack.push((next, probe));
+                    },
+                    None => (),
+                }
+            }
+        }
+
+        maze
+    }
+
+    fn distances(&self, from: (i64, i64)) -> HashMap<(i64, i64), usize> {
+        let mut seen = HashMap::new();
+        let mut queue = VecDeque::new();
+
+        seen.insert(from, 0);
+        queue.push_back(from);
+
+        while let Some((x, y)) = queue.pop_front() {
+            let d = seen[&(x, y)];
+
+            for (_, (dx, dy)) in MOVES.iter() {
+                let next = (x + dx, y + dy);
+
+                match self.map.get(&next) {
+                    Some(Tile::Open) | Some(Tile::Oxygen) if !seen.contains_key(&next) => {
+                        seen.insert(next, d + 1);
+                        queue.push_back(next);
+                    },
+                    _ => (),
+                }
+            }
+        }
+
+        seen
+    }
+
+    pub fn shortest_path(&self, from: (i64, i64), to: (i64, i64)) -> Option<usize> {
+        self.distances(from).get(&to).cloned()
+    }
+
+    // Minutes for oxygen to fill every open cell reachable from `from`.
+    pub fn fill_time(&self, from: (i64, i64)) -> usize {
+        self.distances(from).values().cloned().max().unwrap_or(0)
+    }
+
+    pub fn screen(&self) -> Screen {
+        let mut screen = Screen::new(Palette::new()
+            .tile(0, '#', [128, 128, 128])
+            .tile(1, '.', [0, 0, 0])
+            .tile(2, 'O', [0, 128, 255])
+            .tile(3, 'D', [255, 255, 0]));
+
+        for (pos, tile) in self.map.iter() {
+            let id = match tile {
+                Tile::Wall => 0,
+                Tile::Open => 1,
+                Tile::Oxygen => 2,
+            };
+            screen.tiles.insert(*pos, id);
+        }
+        screen.tiles.insert((0, 0), 3);
+
+        screen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Memory;
+
+    // A corridor four cells long running east from the start, oxygen at the far end.
+    fn droid() -> IntCode {
+        let buf = vec![3,201,1008,201,4,202,1005,202,21,1008,201,3,202,1005,202,35,104,0,1105,1,0,1007,200,3,202,1006,202,49,1001,200,1,200,1105,1,54,107,0,200,202,1006,202,49,1001,200,-1,200,1105,1,54,104,0,1105,1,0,1008,200,3,202,1001,202,1,202,4,202,1105,1,0];
+        let memory = Memory::new(buf);
+
+        IntCode::new(memory)
+    }
+
+    #[test]
+    fn explores_corridor() {
+        let maze = Maze::explore(droid());
+
+        assert_eq!(maze.oxygen, Some((3, 0)));
+        assert_eq!(maze.map.values().filter(|t| **t == Tile::Wall).count(), 10);
+        assert_eq!(maze.shortest_path((0, 0), (3, 0)), Some(3));
+        assert_eq!(maze.fill_time((3, 0)), 3);
+        assert_eq!(maze.screen().render(), "######\n#D..O#\n######");
+    }
+}
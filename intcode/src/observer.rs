@@ -0,0 +1,156 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use super::{IntCode, Instruction};
+
+// Callbacks from the VM core. Every hook defaults to doing nothing so an
+// observer only implements what it cares about.
+pub trait Observer {
+    fn fetch(&mut self, _addr: usize, _instruction: &Instruction) {}
+    // The fetched instruction is about to run as `step`, with the registers
+    // as they are before it.
+    fn begin(&mut self, _step: usize, _ic: usize, _relative_base: usize) {}
+    fn read(&mut self, _addr: usize, _value: i64) {}
+    fn write(&mut self, _addr: usize, _old: i64, _new: i64) {}
+    fn input(&mut self, _value: i64) {}
+    fn output(&mut self, _value: i64) {}
+    fn relative_base(&mut self, _base: usize) {}
+}
+
+// The observers attached to a machine. They are shared so the caller keeps a
+// handle to read results from, and so a cloned machine keeps reporting to them
+// (the journal and the replay are the exception, see `IntCode`'s `Clone`).
+// With none attached every hook is a loop over an empty Vec.
+#[derive(Clone, Default)]
+pub struct Observers(Vec<Rc<RefCell<dyn Observer>>>);
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+impl Observers {
+    pub fn fetch(&self, addr: usize, instruction: &Instruction) {
+        for o in self.0.iter() {
+            o.borrow_mut().fetch(addr, instruction);
+        }
+    }
+
+    pub fn begin(&self, step: usize, ic: usize, relative_base: usize) {
+        for o in self.0.iter() {
+            o.borrow_mut().begin(step, ic, relative_base);
+        }
+    }
+
+    pub fn read(&self, addr: usize, value: i64) {
+        for o in self.0.iter() {
+            o.borrow_mut().read(addr, value);
+        }
+    }
+
+    pub fn write(&self, addr: usize, old: i64, new: i64) {
+        for o in self.0.iter() {
+            o.borrow_mut().write(addr, old, new);
+        }
+    }
+
+    pub fn input(&self, value: i64) {
+        for o in self.0.iter() {
+            o.borrow_mut().input(value);
+        }
+    }
+
+    pub fn output(&self, value: i64) {
+        for o in self.0.iter() {
+            o.borrow_mut().output(value);
+        }
+    }
+
+    pub fn relative_base(&self, base: usize) {
+        for o in self.0.iter() {
+            o.borrow_mut().relative_base(base);
+        }
+    }
+}
+
+impl IntCode {
+    // Attaches an observer and hands back a handle to it.
+    pub fn observe<O: Observer + 'static>(&mut self, observer: O) -> Rc<RefCell<O>> {
+        let shared = Rc::new(RefCell::new(observer));
+
+        self.observers.0.push(shared.clone());
+        shared
+    }
+
+    // Detaches an observer attached with `observe`.
+    pub fn unobserve<O: Observer + 'static>(&mut self, observer: &Rc<RefCell<O>>) {
+        let target = Rc::as_ptr(observer) as *const u8;
+
+        self.observers.0.retain(|o| Rc::as_ptr(o) as *const u8 != target);
+    }
+}
+
+// Collects one line per instruction and per memory write into `lines`.
+#[derive(Debug, Default)]
+pub struct Trace {
+    pub lines: Vec<String>,
+}
+
+impl Observer for Trace {
+    fn fetch(&mut self, addr: usize, instruction: &Instruction) {
+        self.lines.push(format!("{:>6}: {}", addr, instruction));
+    }
+
+    fn write(&mut self, addr: usize, old: i64, new: i64) {
+        self.lines.push(format!("        [{}] {} -> {}", addr, old, new));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Memory;
+
+    #[derive(Default)]
+    struct Watch {
+        addr: usize,
+        hits: Vec<i64>,
+        io: Vec<i64>,
+    }
+
+    impl Observer for Watch {
+        fn write(&mut self, addr: usize, _old: i64, new: i64) {
+            if addr == self.addr {
+                self.hits.push(new);
+            }
+        }
+
+        fn input(&mut self, value: i64) {
+            self.io.push(value);
+        }
+
+        fn output(&mut self, value: i64) {
+            self.io.push(-value);
+        }
+    }
+
+    #[test]
+    fn watch_and_trace() {
+        let memory = Memory::new(vec![3,9,8,9,10,9,4,9,99,-1,8]);
+        let mut intcode = IntCode::new(memory);
+        let watch = intcode.observe(Watch { addr: 9, ..Default::default() });
+        let trace = intcode.observe(Trace::default());
+
+        intcode.run_program(&mut vec![8]);
+
+        assert_eq!(watch.borrow().hits, vec![8, 1]);
+        assert_eq!(watch.borrow().io, vec![8, -1]);
+        assert_eq!(trace.borrow().lines[..2], [
+            String::from("     0: in [9]"),
+            String::from("        [9] -1 -> 8"),
+        ]);
+        assert_eq!(trace.borrow().lines.last().map(|l| l.as_str()), Some("     8: hlt"));
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+use super::builder::Operand::{self, Immediate, Position};
+use super::disasm::decode;
+use super::observer::Observer;
+use super::{IntCode, Instruction, Memory, OpCode};
+
+// Programs address their own code with absolute numbers, so every rewrite keeps
+// the layout as it is: an instruction is only ever replaced by one of the same
+// length. Removing a jump means pointing everything that lands on it past it,
+// or turning it into the `hlt` it would reach.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rewrite {
+    pub addr: usize,
+    pub what: &'static str,
+    pub before: Instruction,
+    pub after: Instruction,
+}
+
+impl fmt::Display for Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>6}: {:<28} -> {} ({})", self.addr, self.before.to_string(), self.after, self.what)
+    }
+}
+
+#[derive(Debug)]
+pub struct Optimized {
+    pub image: Vec<i64>,
+    pub rewrites: Vec<Rewrite>,
+}
+
+// What can be said about a program before running it.
+struct Analysis {
+    // Instructions reachable from 0 through immediate jumps, plus code after
+    // an unconditional jump whose address shows up as a constant.
+    starts: BTreeSet<usize>,
+    // Every immediate operand, any of them could end up as a jump target.
+    targets: HashSet<usize>,
+    // Cells some instruction reads or writes as data through a position operand.
+    touched: HashSet<usize>,
+}
+
+fn constant(operand: Option<Operand>) -> Option<i64> {
+    match operand? {
+        Immediate(v) => Some(v),
+        _ => None,
+    }
+}
+
+fn is_jump(op: OpCode) -> bool {
+    matches!(op, OpCode::JumpIfTrue | OpCode::JumpIfFalse)
+}
+
+fn rebuild(jump: OpCode, test: Operand, target: Operand) -> Result<Instruction, String> {
+    match jump {
+        OpCode::JumpIfTrue => Instruction::jump_if_true(test, target),
+        _ => Instruction::jump_if_false(test, target),
+    }
+}
+
+impl Analysis {
+    fn new(program: &[i64]) -> Self {
+        let mut analysis = Analysis {
+            starts: BTreeSet::new(),
+            targets: HashSet::new(),
+            touched: HashSet::new(),
+        };
+        // Where to look once control flow runs out: right after unconditional
+        // jumps, which is where calls return to.
+        let mut after = Vec::new();
+        let mut pending = vec![0];
+
+        loop {
+            while let Some(addr) = pending.pop() {
+                if addr >= program.len() || !analysis.starts.insert(addr) {
+                    continue;
+                }
+
+                let i = decode(program, addr);
+
+                for n in 0..i.len.saturating_sub(1) {
+                    match i.operand(n) {
+                        Some(Immediate(v)) if v >= 0 => { analysis.targets.insert(v as usize); },
+                        Some(Position(a)) if a >= 0 => { analysis.touched.insert(a as usize); },
+                        _ => (),
+                    }
+                }
+
+                match i.op {
+                    OpCode::Unknown | OpCode::Halt => (),
+                    op if is_jump(op) => {
+                        if let Some(t) = constant(i.operand(1)) {
+                            pending.push(t as usize);
+                        }
+
+                        let always = match (op, constant(i.operand(0))) {
+                            (OpCode::JumpIfTrue, Some(v)) => v != 0,
+                            (OpCode::JumpIfFalse, Some(v)) => v == 0,
+                            _ => false,
+                        };
+
+                        if always {
+                            after.push(addr + i.len);
+                        } else {
+                            pending.push(addr + i.len);
+                        }
+                    },
+                    _ => pending.push(addr + i.len),
+                }
+            }
+
+            let found = after.iter().filter(|a| analysis.targets.contains(a)).cloned().collect::<Vec<usize>>();
+
+            after.retain(|a| !analysis.targets.contains(a));
+            if found.is_empty() {
+                break;
+            }
+            pending = found;
+        }
+
+        analysis
+    }
+
+    // Code we may change: found by the sweep, and no instruction reads or
+    // overwrites any of its words as data.
+    fn fixed(&self, addr: usize, len: usize) -> bool {
+        self.starts.contains(&addr) && (addr..addr + len).all(|a| !self.touched.contains(&a))
+    }
+}
+
+struct Optimizer {
+    image: Vec<i64>,
+    analysis: Analysis,
+    rewrites: Vec<Rewrite>,
+}
+
+impl Optimizer {
+    fn at(&self, addr: usize) -> Option<Instruction> {
+        let i = decode(&self.image, addr);
+
+        if i.op == OpCode::Unknown || addr + i.len > self.image.len() || !self.analysis.fixed(addr, i.len) {
+            return None;
+        }
+
+        Some(i)
+    }
+
+    // A test that can be read without faulting, so dropping the jump that
+    // reads it doesn't hide a fault.
+    fn readable(&self, test: Option<Operand>) -> bool {
+        match test {
+            Some(Immediate(_)) => true,
+            Some(Position(a)) => a >= 0 && (a as usize) < self.image.len(),
+            _ => false,
+        }
+    }
+
+    fn replace(&mut self, addr: usize, what: &'static str, after: Instruction) {
+        let before = decode(&self.image, addr);
+
+        if before == after {
+            return;
+        }
+
+        self.image[addr..addr + after.len].copy_from_slice(&after.encode());
+        self.rewrites.push(Rewrite { addr, what, before, after });
+    }
+
+    // Saves no steps by itself, constant arithmetic just becomes a plain
+    // `add v, 0, dst` store that reads the same everywhere.
+    fn fold(&mut self, addr: usize) {
+        let i = match self.at(addr) {
+            Some(i) => i,
+            None => return,
+        };
+        let (a, b) = match (constant(i.operand(0)), constant(i.operand(1))) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return,
+        };
+        let value = match i.op {
+            OpCode::Add => a.checked_add(b),
+            OpCode::Mul => a.checked_mul(b),
+            OpCode::Equals => Some((a == b) as i64),
+            OpCode::LessThan => Some((a < b) as i64),
+            _ => None,
+        };
+
+        // An immediate destination is written as a position by the lenient
+        // VM, the builder won't encode that so the instruction stays as it is.
+        if let (Some(value), Some(dst)) = (value, i.operand(2)) {
+            if let Ok(after) = Instruction::add(Immediate(value), Immediate(0), dst) {
+                self.replace(addr, "fold", after);
+            }
+        }
+    }
+
+    // `eq x, y, [t]` straight into a jump on `[t]`. Nothing may enter at the
+    // jump, otherwise `[t]` could have come from somewhere else.
+    fn branch(&mut self, addr: usize) {
+        let (eq, jump) = match (self.at(addr), self.at(addr + 4)) {
+            (Some(eq), Some(jump)) if eq.op == OpCode::Equals && is_jump(jump.op) => (eq, jump),
+            _ => return,
+        };
+        let t = match eq.operand(2) {
+            Some(Position(t)) if jump.operand(0) == Some(Position(t)) => t,
+            _ => return,
+        };
+
+        if self.analysis.targets.contains(&(addr + 4)) || (addr as i64..addr as i64 + 7).contains(&t) {
+            return;
+        }
+
+        let (x, y) = (eq.operand(0).unwrap(), eq.operand(1).unwrap());
+        let target = jump.operand(1).unwrap();
+
+        let after = match (constant(Some(x)), constant(Some(y))) {
+            // Known either way, the jump becomes unconditional or never taken.
+            (Some(a), Some(b)) => rebuild(jump.op, Immediate((a == b) as i64), target),
+            // Comparing against zero is what the other jump already does.
+            (_, Some(0)) | (Some(0), _) => {
+                let tested = if y == Immediate(0) { x } else { y };
+                if tested == Position(t) {
+                    return;
+                }
+                let op = if jump.op == OpCode::JumpIfTrue { OpCode::JumpIfFalse } else { OpCode::JumpIfTrue };
+                rebuild(op, tested, target)
+            },
+            _ => return,
+        };
+
+        if let Ok(after) = after {
+            self.replace(addr + 4, "branch", after);
+        }
+    }
+
+    // Follows a chain of jumps that are known to be taken or known not to be,
+    // including jumps to the next instruction whose test can't fault, to where
+    // control really lands.
+    fn resolve(&self, mut target: usize) -> usize {
+        let mut seen = HashSet::new();
+
+        while seen.insert(target) {
+            let i = match self.at(target) {
+                Some(i) if is_jump(i.op) => i,
+                _ => break,
+            };
+            let to = match constant(i.operand(1)) {
+                Some(to) if to >= 0 => to as usize,
+                _ => break,
+            };
+
+            target = match (i.op, constant(i.operand(0))) {
+                _ if to == target + i.len && self.readable(i.operand(0)) => to,
+                (OpCode::JumpIfTrue, Some(v)) => if v != 0 { to } else { target + i.len },
+                (OpCode::JumpIfFalse, Some(v)) => if v == 0 { to } else { target + i.len },
+                _ => break,
+            };
+        }
+
+        target
+    }
+
+    fn thread(&mut self, addr: usize) {
+        let i = match self.at(addr) {
+            Some(i) if is_jump(i.op) => i,
+            _ => return,
+        };
+        let target = match constant(i.operand(1)) {
+            Some(t) if t >= 0 => t as usize,
+            _ => return,
+        };
+        let resolved = self.resolve(target);
+
+        if resolved != target {
+            let test = i.operand(0).unwrap();
+            if let Ok(after) = rebuild(i.op, test, Immediate(resolved as i64)) {
+                self.replace(addr, "thread", after);
+            }
+        }
+    }
+
+    // Jumps whose destination is known, either from a constant test or
+    // because they go to the next instruction and land there either way, and
+    // which end up on a `hlt` become the halt, which costs no step. A test that
+    // could fault keeps its jump.
+    fn halt(&mut self, addr: usize) {
+        let i = match self.at(addr) {
+            Some(i) if is_jump(i.op) => i,
+            _ => return,
+        };
+        let next = addr + i.len;
+        let to = match constant(i.operand(1)) {
+            Some(to) if to >= 0 => to as usize,
+            _ => return,
+        };
+        let lands = match (i.op, i.operand(0)) {
+            (_, test) if to == next && self.readable(test) => next,
+            (OpCode::JumpIfTrue, Some(Immediate(v))) => if v != 0 { to } else { next },
+            (OpCode::JumpIfFalse, Some(Immediate(v))) => if v == 0 { to } else { next },
+            _ => return,
+        };
+
+        if self.at(self.resolve(lands)).is_some_and(|end| end.op == OpCode::Halt) {
+            if let Ok(halt) = Instruction::halt() {
+                self.replace(addr, "halt", halt);
+            }
+        }
+    }
+}
+
+pub fn optimize(program: &[i64]) -> Optimized {
+    let mut optimizer = Optimizer {
+        image: program.to_vec(),
+        analysis: Analysis::new(program),
+        rewrites: Vec::new(),
+    };
+    let starts = optimizer.analysis.starts.iter().cloned().collect::<Vec<usize>>();
+
+    // Branches look at the comparison as written, so they go before folding.
+    for pass in [Optimizer::branch, Optimizer::fold, Optimizer::thread, Optimizer::halt].iter() {
+        for addr in starts.iter() {
+            pass(&mut optimizer, *addr);
+        }
+    }
+
+    Optimized {
+        image: optimizer.image,
+        rewrites: optimizer.rewrites,
+    }
+}
+
+// Catches the original program writing into code the optimizer changed, which
+// the static checks can't see for writes through the relative base.
+struct Guard {
+    cells: HashSet<usize>,
+    hit: Option<usize>,
+}
+
+impl Observer for Guard {
+    fn write(&mut self, addr: usize, _old: i64, _new: i64) {
+        if self.cells.contains(&addr) && self.hit.is_none() {
+            self.hit = Some(addr);
+        }
+    }
+}
+
+// Runs both images on every input and compares the output. Returns the steps
+// each took in total.
+pub fn verify(program: &[i64], optimized: &Optimized, inputs: &[Vec<i64>], limit: usize) -> Result<(usize, usize), String> {
+    let cells = optimized.rewrites.iter()
+        .flat_map(|r| r.addr..r.addr + r.before.len.max(r.after.len))
+        .collect::<HashSet<usize>>();
+    let mut steps = (0, 0);
+
+    for input in inputs.iter() {
+        let mut original = IntCode::new(Memory::new(program.to_vec()));
+        let mut rewritten = IntCode::new(Memory::new(optimized.image.clone()));
+        let guard = original.observe(Guard { cells: cells.clone(), hit: None });
+
+        let expected = original.try_run(&mut input.clone(), limit);
+        let actual = rewritten.try_run(&mut input.clone(), limit);
+
+        if let Some(addr) = guard.borrow().hit {
+            return Err(format!("input {:?}: the program writes to optimized code at {}", input, addr));
+        }
+
+        if expected != actual {
+            return Err(format!("input {:?}: expected {:?}, got {:?}", input, expected, actual));
+        }
+
+        steps.0 += original.steps;
+        steps.1 += rewritten.steps;
+    }
+
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_and_threads() {
+        let mut program = vec![
+            1101,2,3,30,    // [30] = 2 + 3
+            1105,1,7,       // jump to the next instruction
+            1108,4,4,31,    // [31] = 4 == 4
+            1005,31,16,     // always taken because of the line above
+            104,-1,
+            1105,0,0,       // never taken
+            1106,0,25,      // always taken
+            104,-2, 99,
+            4,30, 99,
+        ];
+        program.resize(32, 0);
+
+        let optimized = optimize(&program);
+
+        assert_eq!(optimized.rewrites.iter().map(|r| (r.addr, r.what)).collect::<Vec<_>>(), vec![
+            (11, "branch"), (0, "fold"), (7, "fold"), (11, "thread"),
+        ]);
+        assert_eq!(optimized.rewrites[3].after.to_string(), "jt 1, 25");
+
+        let mut original = IntCode::new(Memory::new(program.clone()));
+        let mut rewritten = IntCode::new(Memory::new(optimized.image.clone()));
+
+        assert_eq!(original.run_program(&mut vec![]), vec![5]);
+        assert_eq!(rewritten.run_program(&mut vec![]), vec![5]);
+        assert_eq!((original.steps, rewritten.steps), (7, 5));
+    }
+
+    #[test]
+    fn jumps_onto_a_halt_become_the_halt() {
+        let program = vec![1105,1,3, 99];
+
+        assert_eq!(verify(&program, &optimize(&program), &[vec![]], 100), Ok((1, 0)));
+
+        let program = vec![
+            1105,1,3,       // jump to the next instruction
+            1106,0,8,       // always taken
+            104,-1,
+            99,
+        ];
+        let optimized = optimize(&program);
+
+        assert_eq!(optimized.rewrites.iter().map(|r| (r.addr, r.what)).collect::<Vec<_>>(), vec![
+            (0, "thread"), (0, "halt"), (3, "halt"),
+        ]);
+        assert_eq!(verify(&program, &optimized, &[vec![]], 100), Ok((2, 0)));
+
+        // reading the test through the relative base faults here, so it stays
+        let program = vec![109,5, 1205,-10,5, 99];
+        assert_eq!(optimize(&program).rewrites.len(), 0);
+
+        // and so do reads from a negative address, whether the jump is the
+        // one that would become the halt or one further down the chain
+        for program in [vec![1006,-1,3, 99], vec![1105,1,3, 1206,-10,6, 104,7, 99]].iter() {
+            let optimized = optimize(program);
+
+            assert_eq!(optimized.rewrites.len(), 0);
+            assert!(verify(program, &optimized, &[vec![]], 100).is_ok());
+        }
+    }
+
+    #[test]
+    fn cut_off_instructions_stay() {
+        let program = vec![21107,-4,22102];
+
+        assert_eq!(optimize(&program).image, program);
+    }
+
+    #[test]
+    fn leaves_self_modifying_code_alone() {
+        // the input patches the add's second operand before it runs
+        let program = vec![3,4, 1101,1,1,20, 4,20,99];
+        let optimized = optimize(&program);
+
+        assert_eq!(optimized.rewrites.len(), 0);
+        assert_eq!(verify(&program, &optimized, &[vec![7]], 100), Ok((3, 3)));
+
+        // the same patch through the relative base, which only a run can see
+        let program = vec![109,8, 21101,0,7,0, 1101,1,1,20, 4,20,99];
+        let optimized = optimize(&program);
+
+        assert_eq!(optimized.rewrites.len(), 2);
+        assert!(verify(&program, &optimized, &[vec![]], 100).unwrap_err().contains("writes to optimized code at 8"));
+    }
+
+    #[test]
+    fn day9_quine_is_unchanged() {
+        let program = vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
+        let optimized = optimize(&program);
+
+        assert_eq!(verify(&program, &optimized, &[vec![]], 10_000).map(|(a, b)| a == b), Ok(true));
+    }
+}
@@ -0,0 +1,158 @@
+use super::builder::Operand;
+use super::lockstep::{Engine, Step, Stopped};
+use super::{Instruction, OpCode};
+
+#[derive(Clone, Copy, Debug)]
+struct Decoded {
+    op: OpCode,
+    len: usize,
+    args: [Operand; 3],
+}
+
+// An engine that decodes each instruction once and keeps it until something
+// writes over it, instead of re-parsing the label on every fetch.
+pub struct Predecoded {
+    mem: Vec<i64>,
+    ic: usize,
+    relative_base: i64,
+    cache: Vec<Option<Decoded>>,
+}
+
+impl Predecoded {
+    // Decodes every cell up front, data included, so later writes have
+    // something to invalidate.
+    pub fn new(program: &[i64]) -> Self {
+        let mut engine = Predecoded {
+            mem: program.to_vec(),
+            ic: 0,
+            relative_base: 0,
+            cache: vec![None; program.len()],
+        };
+
+        for addr in 0..program.len() {
+            engine.decode(addr);
+        }
+
+        engine
+    }
+
+    fn decode(&mut self, addr: usize) -> Decoded {
+        if let Some(Some(decoded)) = self.cache.get(addr) {
+            return *decoded;
+        }
+
+        let mut instruction = Instruction::new(self.read(addr));
+        let mut args = [Operand::Immediate(0); 3];
+
+        for (n, arg) in args.iter_mut().enumerate().take(instruction.len.saturating_sub(1)) {
+            instruction.args[n] = Some(self.read(addr + n + 1));
+            *arg = instruction.operand(n).unwrap();
+        }
+
+        let decoded = Decoded { op: instruction.op, len: instruction.len, args };
+
+        if addr >= self.cache.len() {
+            self.cache.resize(addr + 1, None);
+        }
+        self.cache[addr] = Some(decoded);
+
+        decoded
+    }
+
+    fn address(&self, operand: Operand) -> Option<usize> {
+        let addr = match operand {
+            Operand::Relative(offset) => offset.checked_add(self.relative_base)?,
+            Operand::Position(addr) | Operand::Immediate(addr) => addr,
+        };
+
+        if addr < 0 { None } else { Some(addr as usize) }
+    }
+
+    fn value(&self, operand: Operand) -> Option<i64> {
+        match operand {
+            Operand::Immediate(v) => Some(v),
+            _ => Some(self.read(self.address(operand)?)),
+        }
+    }
+
+    // Any cached instruction overlapping the cell is stale now.
+    fn write(&mut self, addr: usize, value: i64) {
+        if addr >= self.mem.len() {
+            self.mem.resize(addr + 1, 0);
+        }
+        self.mem[addr] = value;
+
+        for start in addr.saturating_sub(3)..=addr {
+            if let Some(entry) = self.cache.get_mut(start) {
+                *entry = None;
+            }
+        }
+    }
+}
+
+impl Engine for Predecoded {
+    fn step(&mut self, input: &mut Vec<i64>) -> Result<Step, Stopped> {
+        let d = self.decode(self.ic);
+
+        match d.op {
+            OpCode::Halt => Err(Stopped::Halted),
+            OpCode::Input if input.is_empty() => Err(Stopped::NeedInput),
+            _ => self.execute(d, input).ok_or(Stopped::Fault),
+        }
+    }
+
+    fn read(&self, addr: usize) -> i64 {
+        self.mem.get(addr).cloned().unwrap_or(0)
+    }
+}
+
+impl Predecoded {
+    // Runs one instruction, None when it faults.
+    fn execute(&mut self, d: Decoded, input: &mut Vec<i64>) -> Option<Step> {
+        let mut writes = Vec::new();
+        let mut output = None;
+        let mut next = self.ic + d.len;
+
+        match d.op {
+            OpCode::Add | OpCode::Mul | OpCode::LessThan | OpCode::Equals => {
+                let (a, b) = (self.value(d.args[0])?, self.value(d.args[1])?);
+                let result = match d.op {
+                    OpCode::Add => a.checked_add(b)?,
+                    OpCode::Mul => a.checked_mul(b)?,
+                    OpCode::LessThan => (a < b) as i64,
+                    _ => (a == b) as i64,
+                };
+
+                writes.push((self.address(d.args[2])?, result));
+            },
+            OpCode::Input => {
+                writes.push((self.address(d.args[0])?, input.remove(0)));
+            },
+            OpCode::Output => output = Some(self.value(d.args[0])?),
+            OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                let (test, target) = (self.value(d.args[0])?, self.value(d.args[1])?);
+
+                if (test != 0) == (d.op == OpCode::JumpIfTrue) {
+                    if target < 0 {
+                        return None;
+                    }
+                    next = target as usize;
+                }
+            },
+            OpCode::RelativeBase => self.relative_base = self.relative_base.checked_add(self.value(d.args[0])?)?,
+            OpCode::Halt | OpCode::Unknown => return None,
+        }
+
+        for (addr, value) in writes.iter() {
+            self.write(*addr, *value);
+        }
+        self.ic = next;
+
+        Some(Step {
+            ic: self.ic,
+            relative_base: self.relative_base as usize,
+            writes,
+            output,
+        })
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use super::observer::Observer;
+use super::{Instruction, OpCode};
+
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub executed: HashMap<usize, u64>,
+    pub opcodes: HashMap<OpCode, u64>,
+    pub reads: HashMap<usize, u64>,
+    pub writes: HashMap<usize, u64>,
+    pub max_relative_base: usize,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile::default()
+    }
+
+    pub fn fetch(&mut self, addr: usize, op: OpCode) {
+        *self.executed.entry(addr).or_insert(0) += 1;
+        *self.opcodes.entry(op).or_insert(0) += 1;
+    }
+
+    pub fn read(&mut self, addr: usize) {
+        *self.reads.entry(addr).or_insert(0) += 1;
+    }
+
+    pub fn write(&mut self, addr: usize) {
+        *self.writes.entry(addr).or_insert(0) += 1;
+    }
+
+    pub fn relative_base(&mut self, base: usize) {
+        self.max_relative_base = self.max_relative_base.max(base);
+    }
+
+    pub fn total(&self) -> u64 {
+        self.opcodes.values().sum()
+    }
+
+    pub fn report(&self, top: usize) -> String {
+        let mut report = format!("instructions executed: {}\n", self.total());
+        report.push_str(&format!("max relative base: {}\n", self.max_relative_base));
+
+        report.push_str("\nopcodes:\n");
+        for (op, count) in hot(&self.opcodes, top) {
+            report.push_str(&format!("{:>12} {}\n", count, op));
+        }
+
+        for (title, counts) in [("addresses", &self.executed), ("reads", &self.reads), ("writes", &self.writes)].iter() {
+            report.push_str(&format!("\nhot {}:\n", title));
+            for (addr, count) in hot(counts, top) {
+                report.push_str(&format!("{:>12} {}\n", count, addr));
+            }
+        }
+
+        report
+    }
+}
+
+impl Observer for Profile {
+    fn fetch(&mut self, addr: usize, instruction: &Instruction) {
+        Profile::fetch(self, addr, instruction.op);
+    }
+
+    fn read(&mut self, addr: usize, _value: i64) {
+        Profile::read(self, addr);
+    }
+
+    fn write(&mut self, addr: usize, _old: i64, _new: i64) {
+        Profile::write(self, addr);
+    }
+
+    fn relative_base(&mut self, base: usize) {
+        Profile::relative_base(self, base);
+    }
+}
+
+// Highest counts first, ties broken by key so reports are stable between runs.
+pub fn hot<K: Copy + Ord + Hash>(counts: &HashMap<K, u64>, top: usize) -> Vec<(K, u64)> {
+    let mut sorted = counts.iter()
+        .map(|(k, v)| (*k, *v))
+        .collect::<Vec<(K, u64)>>();
+
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    sorted.truncate(top);
+
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{IntCode, Memory};
+
+    #[test]
+    fn counts_loop() {
+        let buf = vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
+        let memory = Memory::new(buf);
+        let mut intcode = IntCode::new(memory);
+        let profile = intcode.observe(Profile::new());
+
+        intcode.run_program(&mut vec![]);
+
+        let profile = profile.borrow();
+
+        assert_eq!(profile.executed[&2], 16);
+        assert_eq!(profile.opcodes[&OpCode::RelativeBase], 16);
+        assert_eq!(profile.opcodes[&OpCode::Halt], 1);
+        assert_eq!(profile.writes[&100], 16);
+        assert_eq!(profile.max_relative_base, 16);
+        assert_eq!(profile.total(), 16 * 5 + 1);
+    }
+
+    #[test]
+    fn hot_spots_sorted() {
+        let mut counts = HashMap::new();
+        counts.insert(7, 2);
+        counts.insert(3, 9);
+        counts.insert(5, 2);
+
+        assert_eq!(hot(&counts, 2), vec![(3, 9), (5, 2)]);
+    }
+}
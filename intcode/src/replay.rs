@@ -0,0 +1,242 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use super::observer::Observer;
+use super::{IntCode, Memory};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Input { step: usize, value: i64 },
+    Output { step: usize, value: i64 },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Replay {
+    pub events: Vec<Event>,
+    // The step running while attached to a machine.
+    step: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub index: usize,
+    pub expected: Option<Event>,
+    pub actual: Option<Event>,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Input { step, value } => write!(f, "in {} {}", step, value),
+            Event::Output { step, value } => write!(f, "out {} {}", step, value),
+        }
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for event in self.events.iter() {
+            writeln!(f, "{}", event)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |e: &Option<Event>| e.map_or(String::from("nothing"), |e| e.to_string());
+
+        write!(f, "event {}: expected {}, got {}", self.index, show(&self.expected), show(&self.actual))
+    }
+}
+
+impl Replay {
+    pub fn new() -> Self {
+        Replay::default()
+    }
+
+    pub fn input(&mut self, step: usize, value: i64) {
+        self.events.push(Event::Input { step, value });
+    }
+
+    pub fn output(&mut self, step: usize, value: i64) {
+        self.events.push(Event::Output { step, value });
+    }
+
+    // Forgets everything that happened after `step`, used when the machine is wound back.
+    pub fn truncate(&mut self, step: usize) {
+        self.events.retain(|e| match e {
+            Event::Input { step: s, .. } | Event::Output { step: s, .. } => *s <= step,
+        });
+    }
+
+    pub fn inputs(&self) -> Vec<i64> {
+        self.events.iter()
+            .filter_map(|e| match e {
+                Event::Input { value, .. } => Some(*value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn parse(text: &str) -> Result<Replay, String> {
+        let mut replay = Replay::new();
+
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let parts = line.split_whitespace().collect::<Vec<&str>>();
+            let bad = || format!("Bad replay entry: {}", line);
+
+            let step = parts.get(1).and_then(|p| p.parse::<usize>().ok()).ok_or_else(bad)?;
+            let value = parts.get(2).and_then(|p| p.parse::<i64>().ok()).ok_or_else(bad)?;
+
+            match parts[0] {
+                "in" => replay.input(step, value),
+                "out" => replay.output(step, value),
+                _ => return Err(format!("Bad replay entry: {}", line)),
+            }
+        }
+
+        Ok(replay)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Replay> {
+        Replay::parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+impl Observer for Replay {
+    fn begin(&mut self, step: usize, _ic: usize, _relative_base: usize) {
+        self.step = step;
+    }
+
+    fn input(&mut self, value: i64) {
+        self.events.push(Event::Input { step: self.step, value });
+    }
+
+    fn output(&mut self, value: i64) {
+        self.events.push(Event::Output { step: self.step, value });
+    }
+}
+
+impl IntCode {
+    // Starts recording the machine's input and output and hands back the log.
+    pub fn record(&mut self) -> Rc<RefCell<Replay>> {
+        let replay = self.observe(Replay::new());
+
+        self.replay = Some(replay.clone());
+        replay
+    }
+}
+
+// Re-runs `program` feeding it the recorded inputs and checks that every input is
+// consumed and every output produced at the same step as in the recording. The
+// run ends when the program halts, runs out of input, faults or has taken
+// `limit` steps, whatever it did up to there is what gets compared.
+pub fn verify(program: &[i64], log: &Replay, limit: usize) -> Result<(), Divergence> {
+    let memory = Memory::new(program.to_vec());
+    let mut intcode = IntCode::new(memory);
+    let mut input = log.inputs();
+    let mut output = Vec::new();
+
+    let replay = intcode.record();
+
+    while let Ok(true) = intcode.try_step(&mut input, &mut output, limit) {}
+
+    let actual = replay.borrow().events.clone();
+    let len = actual.len().max(log.events.len());
+
+    match (0..len).find(|n| actual.get(*n) != log.events.get(*n)) {
+        Some(index) => Err(Divergence {
+            index,
+            expected: log.events.get(index).cloned(),
+            actual: actual.get(index).cloned(),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Amplifier;
+
+    fn recording(buf: &[i64]) -> IntCode {
+        let memory = Memory::new(buf.to_vec());
+        let mut intcode = IntCode::new(memory);
+        intcode.record();
+
+        intcode
+    }
+
+    #[test]
+    fn record_and_verify() {
+        let buf = vec![3,9,8,9,10,9,4,9,99,-1,8];
+        let mut intcode = recording(&buf);
+
+        intcode.run_program(&mut vec![8]);
+
+        let log = intcode.replay.unwrap().borrow().clone();
+
+        assert_eq!(log.to_string(), "in 1 8\nout 3 1\n");
+        assert_eq!(verify(&buf, &log, 1000), Ok(()));
+    }
+
+    #[test]
+    fn detects_divergence() {
+        let buf = vec![3,9,8,9,10,9,4,9,99,-1,8];
+        let log = Replay::parse("in 1 7\nout 3 1\n").unwrap();
+
+        assert_eq!(verify(&buf, &log, 1000), Err(Divergence {
+            index: 1,
+            expected: Some(Event::Output { step: 3, value: 1 }),
+            actual: Some(Event::Output { step: 3, value: 0 }),
+        }));
+    }
+
+    #[test]
+    fn stops_at_the_limit_and_rejects_negative_steps() {
+        // loops forever instead of reading the input it was recorded with
+        let buf = vec![1105,1,0,3,9,99];
+        let log = Replay::parse("in 1 7\n").unwrap();
+
+        assert_eq!(verify(&buf, &log, 1000), Err(Divergence {
+            index: 0,
+            expected: Some(Event::Input { step: 1, value: 7 }),
+            actual: None,
+        }));
+        assert_eq!(Replay::parse("in -1 7\n").err().as_deref(), Some("Bad replay entry: in -1 7"));
+    }
+
+    #[test]
+    fn feedback_session() {
+        let buf = vec![3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5];
+        let mut amps = [9,8,7,6,5].iter()
+            .map(|phase| Amplifier::new(*phase, recording(&buf)))
+            .collect::<Vec<Amplifier>>();
+        let mut value = 0;
+
+        while let Some(v) = amps.iter_mut().try_fold(value, |v, amp| amp.run(v)) {
+            value = v;
+        }
+
+        assert_eq!(value, 139629729);
+
+        for amp in amps.iter_mut() {
+            let log = amp.cpu.replay.take().unwrap().borrow().clone();
+            let path = std::env::temp_dir().join(format!("intcode-replay-{}", amp.phase));
+
+            log.save(&path).unwrap();
+            assert_eq!(verify(&buf, &Replay::load(&path).unwrap(), 1000), Ok(()));
+        }
+    }
+}
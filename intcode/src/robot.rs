@@ -0,0 +1,134 @@
+use std::collections::{HashMap, HashSet};
+
+use super::screen::{Palette, Screen};
+use super::{IntCode, Status};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Right,
+    Down,
+    Left,
+}
+
+impl Direction {
+    // 0 turns left, anything else turns right.
+    pub fn turn(self, towards: i64) -> Self {
+        match (self, towards) {
+            (Direction::Up, 0) => Direction::Left,
+            (Direction::Left, 0) => Direction::Down,
+            (Direction::Down, 0) => Direction::Right,
+            (Direction::Right, 0) => Direction::Up,
+            (Direction::Up, _) => Direction::Right,
+            (Direction::Right, _) => Direction::Down,
+            (Direction::Down, _) => Direction::Left,
+            (Direction::Left, _) => Direction::Up,
+        }
+    }
+
+    // y grows downwards so panels render the right way up.
+    pub fn step(self, (x, y): (i64, i64)) -> (i64, i64) {
+        match self {
+            Direction::Up => (x, y - 1),
+            Direction::Right => (x + 1, y),
+            Direction::Down => (x, y + 1),
+            Direction::Left => (x - 1, y),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Robot {
+    pub pos: (i64, i64),
+    pub facing: Direction,
+    pub panels: HashMap<(i64, i64), i64>,
+    pub painted: HashSet<(i64, i64)>,
+}
+
+impl Default for Robot {
+    fn default() -> Self {
+        Robot {
+            pos: (0, 0),
+            facing: Direction::Up,
+            panels: HashMap::new(),
+            painted: HashSet::new(),
+        }
+    }
+}
+
+impl Robot {
+    pub fn new() -> Self {
+        Robot::default()
+    }
+
+    pub fn color(&self) -> i64 {
+        self.panels.get(&self.pos).cloned().unwrap_or(0)
+    }
+
+    // Feeds the color under the robot whenever the program asks for it and
+    // reads a paint color followed by a turn for every move. Returns the number
+    // of moves made before the program halted.
+    pub fn run(&mut self, cpu: &mut IntCode) -> usize {
+        let mut input = Vec::new();
+        let mut moves = 0;
+
+        loop {
+            let paint = match cpu.resume(&mut input) {
+                Status::NeedInput => {
+                    input.push(self.color());
+                    continue;
+                },
+                Status::Output(paint) => paint,
+                Status::Halted => return moves,
+            };
+
+            self.panels.insert(self.pos, paint);
+            self.painted.insert(self.pos);
+
+            match cpu.resume(&mut input) {
+                Status::Output(turn) => {
+                    self.facing = self.facing.turn(turn);
+                    self.pos = self.facing.step(self.pos);
+                    moves += 1;
+                },
+                _ => return moves,
+            }
+        }
+    }
+
+    pub fn screen(&self) -> Screen {
+        let mut screen = Screen::new(Palette::hull());
+
+        screen.tiles = self.panels.clone();
+        screen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Memory;
+
+    #[test]
+    fn paints_a_square() {
+        // paints white and turns left four times, then inverts the color it finds
+        let buf = vec![3,100,104,1,104,0,1001,101,1,101,1007,101,4,102,1005,102,0,3,100,1002,100,-1,103,1001,103,1,103,4,103,104,1,99];
+        let memory = Memory::new(buf);
+        let mut cpu = IntCode::new(memory);
+        let mut robot = Robot::new();
+
+        assert_eq!(robot.run(&mut cpu), 5);
+        assert_eq!(robot.painted.len(), 4);
+        assert_eq!(robot.panels[&(0, 0)], 0);
+        assert_eq!((robot.pos, robot.facing), ((1, 0), Direction::Right));
+        assert_eq!(robot.screen().render(), "# \n##");
+    }
+
+    #[test]
+    fn turning() {
+        let facing = [1, 1, 0, 1].iter().fold(Direction::Up, |d, t| d.turn(*t));
+
+        assert_eq!(facing, Direction::Down);
+        assert_eq!(Direction::Left.step((0, 0)), (-1, 0));
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(Clone, Debug)]
+pub struct Palette {
+    tiles: HashMap<i64, (char, [u8; 3])>,
+    unknown: (char, [u8; 3]),
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            tiles: HashMap::new(),
+            unknown: ('?', [255, 0, 255]),
+        }
+    }
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        Palette::default()
+    }
+
+    pub fn tile(mut self, id: i64, glyph: char, rgb: [u8; 3]) -> Self {
+        self.tiles.insert(id, (glyph, rgb));
+        self
+    }
+
+    // Tile ids of the day 13 arcade cabinet.
+    pub fn arcade() -> Self {
+        Palette::new()
+            .tile(0, ' ', [0, 0, 0])
+            .tile(1, '#', [128, 128, 128])
+            .tile(2, '=', [200, 120, 40])
+            .tile(3, '-', [255, 255, 255])
+            .tile(4, 'o', [255, 255, 0])
+    }
+
+    // Black and white hull panels.
+    pub fn hull() -> Self {
+        Palette::new()
+            .tile(0, ' ', [0, 0, 0])
+            .tile(1, '#', [255, 255, 255])
+    }
+
+    fn lookup(&self, tile: Option<&i64>) -> (char, [u8; 3]) {
+        match tile {
+            Some(t) => self.tiles.get(t).cloned().unwrap_or(self.unknown),
+            None => self.tiles.get(&0).cloned().unwrap_or((' ', [0, 0, 0])),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Screen {
+    pub tiles: HashMap<(i64, i64), i64>,
+    pub score: Option<i64>,
+    pub score_cell: Option<(i64, i64)>,
+    pub palette: Palette,
+    pending: Vec<i64>,
+    cursor: usize,
+}
+
+impl Screen {
+    pub fn new(palette: Palette) -> Self {
+        Screen {
+            tiles: HashMap::new(),
+            score: None,
+            score_cell: Some((-1, 0)),
+            palette,
+            pending: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    // Reads (x, y, tile) triples, a triple split across calls is completed by the next one.
+    pub fn update(&mut self, output: &[i64]) {
+        self.pending.extend_from_slice(output);
+
+        let complete = self.pending.len() - self.pending.len() % 3;
+        let triples = self.pending.drain(..complete).collect::<Vec<i64>>();
+
+        for t in triples.chunks(3) {
+            let cell = (t[0], t[1]);
+
+            if Some(cell) == self.score_cell {
+                self.score = Some(t[2]);
+            } else {
+                self.tiles.insert(cell, t[2]);
+            }
+        }
+    }
+
+    // Reads a stream of tiles laid out row by row, `width` tiles per row. With
+    // no width there are no rows to lay them out in and they are dropped.
+    pub fn pixels(&mut self, width: usize, output: &[i64]) {
+        if width == 0 {
+            return;
+        }
+
+        for tile in output.iter() {
+            let cell = ((self.cursor % width) as i64, (self.cursor / width) as i64);
+
+            self.tiles.insert(cell, *tile);
+            self.cursor += 1;
+        }
+    }
+
+    pub fn count(&self, tile: i64) -> usize {
+        self.tiles.values().filter(|t| **t == tile).count()
+    }
+
+    pub fn bounds(&self) -> Option<(i64, i64, i64, i64)> {
+        if self.tiles.is_empty() {
+            return None;
+        }
+
+        let xs = self.tiles.keys().map(|(x, _)| *x);
+        let ys = self.tiles.keys().map(|(_, y)| *y);
+
+        Some((xs.clone().min()?, ys.clone().min()?, xs.max()?, ys.max()?))
+    }
+
+    fn rows(&self) -> Vec<Vec<(char, [u8; 3])>> {
+        let (x0, y0, x1, y1) = match self.bounds() {
+            Some(b) => b,
+            None => return Vec::new(),
+        };
+
+        (y0..=y1)
+            .map(|y| (x0..=x1).map(|x| self.palette.lookup(self.tiles.get(&(x, y)))).collect())
+            .collect()
+    }
+
+    pub fn render(&self) -> String {
+        let mut frame = self.rows().iter()
+            .map(|row| row.iter().map(|(glyph, _)| *glyph).collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        if let Some(score) = self.score {
+            frame.push_str(&format!("\nScore: {}", score));
+        }
+
+        frame
+    }
+
+    // Redraws the frame in place on an ANSI terminal.
+    pub fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "\x1b[2J\x1b[H{}", self.render())?;
+        out.flush()
+    }
+
+    pub fn ppm(&self, scale: usize) -> Vec<u8> {
+        let rows = self.rows();
+        let height = rows.len() * scale;
+        let width = rows.first().map_or(0, |r| r.len()) * scale;
+        let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+
+        for row in rows.iter() {
+            for _ in 0..scale {
+                for (_, rgb) in row.iter() {
+                    for _ in 0..scale {
+                        image.extend_from_slice(rgb);
+                    }
+                }
+            }
+        }
+
+        image
+    }
+
+    pub fn save_ppm(&self, path: &Path, scale: usize) -> io::Result<()> {
+        fs::write(path, self.ppm(scale))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triples_and_score() {
+        let mut screen = Screen::new(Palette::arcade());
+
+        screen.update(&[1, 2, 3, 6, 5]);
+        screen.update(&[4, -1, 0, 12345, 0, 0, 1]);
+
+        assert_eq!(screen.count(3), 1);
+        assert_eq!(screen.count(4), 1);
+        assert_eq!(screen.score, Some(12345));
+        assert_eq!(screen.bounds(), Some((0, 0, 6, 5)));
+        assert_eq!(screen.render().lines().nth(2), Some(" -     "));
+        assert_eq!(screen.render().lines().last(), Some("Score: 12345"));
+    }
+
+    #[test]
+    fn pixel_stream() {
+        let mut screen = Screen::new(Palette::hull());
+
+        screen.pixels(3, &[1, 0, 1, 0]);
+        screen.pixels(3, &[1, 0]);
+        screen.pixels(0, &[1, 1]);
+
+        assert_eq!(screen.render(), "# #\n # ");
+    }
+
+    #[test]
+    fn ppm_image() {
+        let mut screen = Screen::new(Palette::hull());
+        screen.pixels(2, &[1, 0]);
+
+        let image = screen.ppm(2);
+        let header = b"P6\n4 2\n255\n";
+
+        assert_eq!(&image[..header.len()], header);
+        assert_eq!(image.len(), header.len() + 4 * 2 * 3);
+        assert_eq!(&image[header.len()..header.len() + 6], &[255, 255, 255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,598 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+
+use super::{Instruction, OpCode, ParameterMode};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Const(i64),
+    Sym(String),
+    // read through an address that is itself symbolic
+    Load(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Lt(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(v) => write!(f, "{}", v),
+            Expr::Sym(name) => write!(f, "{}", name),
+            Expr::Load(addr) => write!(f, "mem[{}]", addr),
+            Expr::Add(a, b) => write!(f, "({} + {})", a, b),
+            Expr::Mul(a, b) => write!(f, "({} * {})", a, b),
+            Expr::Lt(a, b) => write!(f, "({} < {})", a, b),
+            Expr::Eq(a, b) => write!(f, "({} == {})", a, b),
+        }
+    }
+}
+
+// sum of coeff * symbol plus a constant
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Linear {
+    pub coeffs: BTreeMap<String, i64>,
+    pub constant: i64,
+}
+
+// Constants that would overflow are left unfolded, so the result stays opaque
+// and nothing built on it can be solved.
+impl Expr {
+    pub fn add(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(x), Expr::Const(y)) if x.checked_add(y).is_some() => Expr::Const(x + y),
+            (Expr::Const(0), e) | (e, Expr::Const(0)) => e,
+            (a, b) => Expr::Add(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn mul(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(x), Expr::Const(y)) if x.checked_mul(y).is_some() => Expr::Const(x * y),
+            (Expr::Const(0), _) | (_, Expr::Const(0)) => Expr::Const(0),
+            (Expr::Const(1), e) | (e, Expr::Const(1)) => e,
+            (a, b) => Expr::Mul(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn lt(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(x), Expr::Const(y)) => Expr::Const((x < y) as i64),
+            (a, b) => Expr::Lt(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn eq(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(x), Expr::Const(y)) => Expr::Const((x == y) as i64),
+            (a, b) => Expr::Eq(Box::new(a), Box::new(b)),
+        }
+    }
+
+    // The value with every symbol bound, None when that can't be known here:
+    // a load through memory, an unbound symbol or an overflow.
+    pub fn eval(&self, values: &BTreeMap<String, i64>) -> Option<i64> {
+        match self {
+            Expr::Const(v) => Some(*v),
+            Expr::Sym(name) => values.get(name).cloned(),
+            Expr::Load(_) => None,
+            Expr::Add(a, b) => a.eval(values)?.checked_add(b.eval(values)?),
+            Expr::Mul(a, b) => a.eval(values)?.checked_mul(b.eval(values)?),
+            Expr::Lt(a, b) => Some((a.eval(values)? < b.eval(values)?) as i64),
+            Expr::Eq(a, b) => Some((a.eval(values)? == b.eval(values)?) as i64),
+        }
+    }
+
+    pub fn linear(&self) -> Option<Linear> {
+        match self {
+            Expr::Const(v) => Some(Linear { constant: *v, ..Default::default() }),
+            Expr::Sym(name) => {
+                let mut linear = Linear::default();
+                linear.coeffs.insert(name.clone(), 1);
+                Some(linear)
+            },
+            Expr::Add(a, b) => {
+                let mut sum = a.linear()?;
+                let other = b.linear()?;
+
+                for (name, c) in other.coeffs {
+                    let coeff = sum.coeffs.entry(name).or_insert(0);
+                    *coeff = coeff.checked_add(c)?;
+                }
+                sum.constant = sum.constant.checked_add(other.constant)?;
+                sum.coeffs.retain(|_, c| *c != 0);
+
+                Some(sum)
+            },
+            Expr::Mul(a, b) => {
+                let (a, b) = (a.linear()?, b.linear()?);
+                let (k, mut e) = match (a.coeffs.is_empty(), b.coeffs.is_empty()) {
+                    (true, _) => (a.constant, b),
+                    (_, true) => (b.constant, a),
+                    _ => return None,
+                };
+
+                for c in e.coeffs.values_mut() {
+                    *c = c.checked_mul(k)?;
+                }
+                e.constant = e.constant.checked_mul(k)?;
+                e.coeffs.retain(|_, c| *c != 0);
+
+                Some(e)
+            },
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Constraint {
+    pub expr: Expr,
+    pub nonzero: bool,
+}
+
+impl Constraint {
+    // Whether `values` take the branch this constraint was recorded on. An
+    // expression that can't be evaluated is given the benefit of the doubt.
+    pub fn holds(&self, values: &BTreeMap<String, i64>) -> bool {
+        self.expr.eval(values).is_none_or(|v| (v != 0) == self.nonzero)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum End {
+    Halted,
+    NeedInput,
+    StepLimit,
+    Unsupported(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Path {
+    image: Rc<Vec<i64>>,
+    mem: HashMap<usize, Expr>,
+    ic: usize,
+    relative_base: i64,
+    input: usize,
+    steps: usize,
+    pub outputs: Vec<Expr>,
+    pub constraints: Vec<Constraint>,
+    pub end: Option<End>,
+}
+
+impl Path {
+    pub fn read(&self, addr: usize) -> Expr {
+        match self.mem.get(&addr) {
+            Some(e) => e.clone(),
+            None => Expr::Const(self.image.get(addr).cloned().unwrap_or(0)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Goal {
+    Memory(usize),
+    Output(usize),
+}
+
+pub struct Symbolic {
+    image: Rc<Vec<i64>>,
+    cells: BTreeMap<usize, Expr>,
+    inputs: Vec<Expr>,
+    domains: BTreeMap<String, (i64, i64)>,
+    pub max_steps: usize,
+    pub max_paths: usize,
+}
+
+impl Symbolic {
+    pub fn new(image: &[i64]) -> Self {
+        Symbolic {
+            image: Rc::new(image.to_vec()),
+            cells: BTreeMap::new(),
+            inputs: Vec::new(),
+            domains: BTreeMap::new(),
+            max_steps: 100_000,
+            max_paths: 64,
+        }
+    }
+
+    pub fn cell(mut self, addr: usize, name: &str, lo: i64, hi: i64) -> Self {
+        self.cells.insert(addr, Expr::Sym(String::from(name)));
+        self.domains.insert(String::from(name), (lo, hi));
+        self
+    }
+
+    pub fn input(mut self, name: &str, lo: i64, hi: i64) -> Self {
+        self.inputs.push(Expr::Sym(String::from(name)));
+        self.domains.insert(String::from(name), (lo, hi));
+        self
+    }
+
+    pub fn constant_input(mut self, value: i64) -> Self {
+        self.inputs.push(Expr::Const(value));
+        self
+    }
+
+    // Every path through the program, forking wherever a jump depends on a symbol.
+    pub fn explore(&self) -> Vec<Path> {
+        let start = Path {
+            image: self.image.clone(),
+            mem: self.cells.iter().map(|(a, e)| (*a, e.clone())).collect(),
+            ic: 0,
+            relative_base: 0,
+            input: 0,
+            steps: 0,
+            outputs: Vec::new(),
+            constraints: Vec::new(),
+            end: None,
+        };
+        let mut work = vec![start];
+        let mut done = Vec::new();
+
+        while let Some(mut path) = work.pop() {
+            while path.end.is_none() {
+                if let Some(fork) = self.step(&mut path) {
+                    if work.len() + done.len() < self.max_paths {
+                        work.push(fork);
+                    }
+                }
+            }
+            done.push(path);
+        }
+
+        done
+    }
+
+    fn operand(&self, path: &Path, raw: Expr, mode: &ParameterMode) -> Expr {
+        let addr = match mode {
+            ParameterMode::Immediate => return raw,
+            ParameterMode::Position => raw,
+            ParameterMode::Relative => Expr::add(raw, Expr::Const(path.relative_base)),
+        };
+
+        match addr {
+            Expr::Const(a) if a >= 0 => path.read(a as usize),
+            addr => Expr::Load(Box::new(addr)),
+        }
+    }
+
+    fn destination(&self, path: &Path, raw: Expr, mode: &ParameterMode) -> Result<usize, String> {
+        let addr = match mode {
+            ParameterMode::Relative => Expr::add(raw, Expr::Const(path.relative_base)),
+            _ => raw,
+        };
+
+        match addr {
+            Expr::Const(a) if a >= 0 => Ok(a as usize),
+            addr => Err(format!("write through address {} at {}", addr, path.ic)),
+        }
+    }
+
+    // Executes one instruction, returning the other half of a fork if there was one.
+    fn step(&self, path: &mut Path) -> Option<Path> {
+        if path.steps >= self.max_steps {
+            path.end = Some(End::StepLimit);
+            return None;
+        }
+
+        let label = match path.read(path.ic) {
+            Expr::Const(label) => label,
+            e => {
+                path.end = Some(End::Unsupported(format!("symbolic opcode {} at {}", e, path.ic)));
+                return None;
+            },
+        };
+        let i = Instruction::new(label);
+
+        if i.op == OpCode::Unknown {
+            path.end = Some(End::Unsupported(format!("invalid opcode {} at {}", label, path.ic)));
+            return None;
+        }
+
+        let raw = (0..i.len - 1).map(|n| path.read(path.ic + n + 1)).collect::<Vec<Expr>>();
+        let arg = |n: usize, path: &Path| self.operand(path, raw[n].clone(), &i.modes[n]);
+        let next = path.ic + i.len;
+
+        path.steps += 1;
+
+        let result = match i.op {
+            OpCode::Add | OpCode::Mul | OpCode::LessThan | OpCode::Equals => {
+                let (a, b) = (arg(0, path), arg(1, path));
+                let value = match i.op {
+                    OpCode::Add => Expr::add(a, b),
+                    OpCode::Mul => Expr::mul(a, b),
+                    OpCode::LessThan => Expr::lt(a, b),
+                    _ => Expr::eq(a, b),
+                };
+
+                self.destination(path, raw[2].clone(), &i.modes[2])
+                    .map(|dst| { path.mem.insert(dst, value); })
+            },
+            OpCode::Input => match self.inputs.get(path.input).cloned() {
+                Some(value) => {
+                    path.input += 1;
+                    self.destination(path, raw[0].clone(), &i.modes[0])
+                        .map(|dst| { path.mem.insert(dst, value); })
+                },
+                None => {
+                    path.end = Some(End::NeedInput);
+                    return None;
+                },
+            },
+            OpCode::Output => {
+                let value = arg(0, path);
+                path.outputs.push(value);
+                Ok(())
+            },
+            OpCode::RelativeBase => match arg(0, path) {
+                Expr::Const(v) => match path.relative_base.checked_add(v) {
+                    Some(base) => {
+                        path.relative_base = base;
+                        Ok(())
+                    },
+                    None => Err(format!("relative base overflows at {}", path.ic)),
+                },
+                e => Err(format!("symbolic relative base adjustment {} at {}", e, path.ic)),
+            },
+            OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                let cond = arg(0, path);
+                let target = arg(1, path);
+                let jump_if = i.op == OpCode::JumpIfTrue;
+
+                return match cond {
+                    Expr::Const(c) => {
+                        path.ic = if (c != 0) == jump_if { self.jump(path, &target)? } else { next };
+                        None
+                    },
+                    cond => {
+                        let mut fork = path.clone();
+
+                        fork.constraints.push(Constraint { expr: cond.clone(), nonzero: !jump_if });
+                        fork.ic = next;
+
+                        path.constraints.push(Constraint { expr: cond, nonzero: jump_if });
+                        path.ic = self.jump(path, &target).unwrap_or(path.ic);
+
+                        Some(fork)
+                    },
+                };
+            },
+            _ => {
+                path.end = Some(End::Halted);
+                return None;
+            },
+        };
+
+        match result {
+            Ok(()) => path.ic = next,
+            Err(reason) => path.end = Some(End::Unsupported(reason)),
+        }
+
+        None
+    }
+
+    fn jump(&self, path: &mut Path, target: &Expr) -> Option<usize> {
+        match target {
+            Expr::Const(t) if *t >= 0 => Some(*t as usize),
+            t => {
+                path.end = Some(End::Unsupported(format!("jump to {} at {}", t, path.ic)));
+                None
+            },
+        }
+    }
+
+    fn goal(path: &Path, goal: Goal) -> Option<Expr> {
+        match goal {
+            Goal::Memory(addr) => Some(path.read(addr)),
+            Goal::Output(n) => path.outputs.get(n).cloned(),
+        }
+    }
+
+    // The same problem with every symbol replaced by a value, used to check candidates.
+    fn concrete(&self, values: &BTreeMap<String, i64>) -> Symbolic {
+        let bind = |e: &Expr| match e {
+            Expr::Sym(name) => Expr::Const(values[name]),
+            e => e.clone(),
+        };
+
+        Symbolic {
+            image: self.image.clone(),
+            cells: self.cells.iter().map(|(a, e)| (*a, bind(e))).collect(),
+            inputs: self.inputs.iter().map(bind).collect(),
+            domains: BTreeMap::new(),
+            max_steps: self.max_steps,
+            max_paths: 1,
+        }
+    }
+
+    // Solves `goal == want` on every halting path whose goal is linear in the
+    // symbols. Candidates that break the path's branch constraints belong to
+    // another path and are dropped, the rest are confirmed by a concrete run.
+    pub fn solve(&self, goal: Goal, want: i64) -> Vec<BTreeMap<String, i64>> {
+        let mut solutions = Vec::new();
+
+        for path in self.explore().iter().filter(|p| p.end == Some(End::Halted)) {
+            let linear = match Symbolic::goal(path, goal).and_then(|e| e.linear()) {
+                Some(linear) => linear,
+                None => continue,
+            };
+            let terms = linear.coeffs.iter()
+                .map(|(name, c)| (*c, self.domains[name]))
+                .collect::<Vec<(i64, (i64, i64))>>();
+
+            let rhs = match want.checked_sub(linear.constant) {
+                Some(rhs) => rhs,
+                None => continue,
+            };
+
+            for values in solve_linear(&terms, rhs) {
+                let mut assignment = self.domains.iter()
+                    .map(|(name, (lo, _))| (name.clone(), *lo))
+                    .collect::<BTreeMap<String, i64>>();
+
+                for (name, v) in linear.coeffs.keys().zip(values) {
+                    assignment.insert(name.clone(), v);
+                }
+
+                if !path.constraints.iter().all(|c| c.holds(&assignment)) {
+                    continue;
+                }
+
+                let check = self.concrete(&assignment).explore();
+                let hit = check.first()
+                    .filter(|p| p.end == Some(End::Halted))
+                    .and_then(|p| Symbolic::goal(p, goal));
+
+                if hit == Some(Expr::Const(want)) && !solutions.contains(&assignment) {
+                    solutions.push(assignment);
+                }
+            }
+        }
+
+        solutions.sort();
+        solutions
+    }
+}
+
+fn ext_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if b == 0 {
+        (a.abs(), a.signum(), 0)
+    } else {
+        let (g, x, y) = ext_gcd(b, a % b);
+        (g, y, x - (a / b) * y)
+    }
+}
+
+fn div_floor(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    if (a % b != 0) && ((a < 0) != (b < 0)) { q - 1 } else { q }
+}
+
+fn div_ceil(a: i128, b: i128) -> i128 {
+    -div_floor(-a, b)
+}
+
+// All values inside the domains with sum(coeff * x) == rhs. Two unknowns are
+// solved directly from the general solution of the diophantine equation, any
+// extra unknowns are walked over their domain.
+pub fn solve_linear(terms: &[(i64, (i64, i64))], rhs: i64) -> Vec<Vec<i64>> {
+    match terms.len() {
+        0 => if rhs == 0 { vec![vec![]] } else { vec![] },
+        1 => {
+            let (a, (lo, hi)) = terms[0];
+            match rhs.checked_rem(a) {
+                Some(0) if (lo..=hi).contains(&(rhs / a)) => vec![vec![rhs / a]],
+                _ => vec![],
+            }
+        },
+        2 => {
+            let (a, (xl, xh)) = (terms[0].0 as i128, (terms[0].1 .0 as i128, terms[0].1 .1 as i128));
+            let (b, (yl, yh)) = (terms[1].0 as i128, (terms[1].1 .0 as i128, terms[1].1 .1 as i128));
+            let c = rhs as i128;
+            let (g, p, q) = ext_gcd(a, b);
+
+            if c % g != 0 {
+                return vec![];
+            }
+
+            // x = x0 + dx * t, y = y0 + dy * t
+            let (x0, y0) = (p * (c / g), q * (c / g));
+            let (dx, dy) = (b / g, -a / g);
+            let bounds = |v0: i128, d: i128, lo: i128, hi: i128| if d > 0 {
+                (div_ceil(lo - v0, d), div_floor(hi - v0, d))
+            } else {
+                (div_ceil(hi - v0, d), div_floor(lo - v0, d))
+            };
+            let (t1, t2) = bounds(x0, dx, xl, xh);
+            let (t3, t4) = bounds(y0, dy, yl, yh);
+
+            (t1.max(t3)..=t2.min(t4))
+                .map(|t| vec![(x0 + dx * t) as i64, (y0 + dy * t) as i64])
+                .collect()
+        },
+        _ => {
+            let (a, (lo, hi)) = terms[0];
+
+            (lo..=hi)
+                .flat_map(|v| {
+                    let rest = a.checked_mul(v).and_then(|av| rhs.checked_sub(av));
+
+                    rest.map_or(vec![], |rhs| solve_linear(&terms[1..], rhs)).into_iter().map(move |mut rest| {
+                        rest.insert(0, v);
+                        rest
+                    })
+                })
+                .collect()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noun_verb() {
+        let program = vec![1,0,0,3,1002,1,7,0,1,0,2,0,1001,0,5,0,99];
+        let problem = Symbolic::new(&program)
+            .cell(1, "noun", 0, 99)
+            .cell(2, "verb", 0, 99);
+
+        let paths = problem.explore();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].read(0).to_string(), "(((noun * 7) + verb) + 5)");
+
+        let solutions = problem.solve(Goal::Memory(0), 300);
+
+        assert_eq!(solutions.len(), 15);
+        assert_eq!(solutions[0]["noun"], 28);
+        assert_eq!(solutions[0]["verb"], 99);
+    }
+
+    #[test]
+    fn forks_on_branches() {
+        let program = vec![3,100,1007,100,10,101,1006,101,16,1002,100,2,102,1105,1,20,1001,100,100,102,4,102,99];
+        let problem = Symbolic::new(&program).input("x", -1000, 1000);
+
+        let paths = problem.explore();
+        assert_eq!(paths.len(), 2);
+        assert!(paths.iter().all(|p| p.constraints.len() == 1));
+
+        let solutions = problem.solve(Goal::Output(0), 150);
+
+        assert_eq!(solutions.len(), 1);
+        assert_eq!(solutions[0]["x"], 50);
+        assert_eq!(problem.solve(Goal::Output(0), 14)[0]["x"], 7);
+    }
+
+    #[test]
+    fn constraints_and_overflow() {
+        let program = vec![3,100,1007,100,10,101,1006,101,16,1002,100,2,102,1105,1,20,1001,100,100,102,4,102,99];
+        let paths = Symbolic::new(&program).input("x", -1000, 1000).explore();
+        let x = |v: i64| vec![(String::from("x"), v)].into_iter().collect::<BTreeMap<String, i64>>();
+
+        // the doubling path is the one taken for x < 10
+        let doubling = paths.iter().find(|p| p.outputs[0].to_string() == "(x * 2)").unwrap();
+        assert!(doubling.constraints[0].holds(&x(9)));
+        assert!(!doubling.constraints[0].holds(&x(10)));
+
+        // constants too big to fold stay opaque instead of panicking
+        let program = vec![1101,9223372036854775807,1,20, 109,9223372036854775807, 109,1, 4,20, 99];
+        let problem = Symbolic::new(&program);
+        let paths = problem.explore();
+
+        assert_eq!(paths[0].read(20).to_string(), "(9223372036854775807 + 1)");
+        assert_eq!(paths[0].end, Some(End::Unsupported(String::from("relative base overflows at 6"))));
+        assert_eq!(problem.solve(Goal::Memory(20), 0), Vec::<BTreeMap<String, i64>>::new());
+        assert_eq!(solve_linear(&[(i64::MAX, (0, 2)), (1, (0, 2)), (1, (0, 2))], -5), Vec::<Vec<i64>>::new());
+    }
+
+    #[test]
+    fn diophantine() {
+        assert_eq!(solve_linear(&[(3, (0, 10)), (5, (0, 10))], 19), vec![vec![3, 2]]);
+        assert_eq!(solve_linear(&[(2, (0, 10)), (4, (0, 10))], 7), Vec::<Vec<i64>>::new());
+        assert_eq!(solve_linear(&[(1, (0, 2)), (1, (0, 2)), (1, (0, 2))], 6), vec![vec![2, 2, 2]]);
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{IntCode, Memory, Policy};
+
+// A single declarative test, written in a case file as:
+//
+//     test equal_to_eight
+//     program 3,9,8,9,10,9,4,9,99,-1,8
+//     input 8
+//     output 1
+//     memory 9=1
+//
+// `error <name>` expects the run to fault (see `Fault::name`), `limit <n>` caps
+// the number of steps and a bare `strict` rejects illegal modes and immediate
+// destinations instead of tolerating them. Lines starting with `#` are comments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Case {
+    pub name: String,
+    pub program: Vec<i64>,
+    pub input: Vec<i64>,
+    pub output: Option<Vec<i64>>,
+    pub memory: Vec<(usize, i64)>,
+    pub error: Option<String>,
+    pub limit: Option<usize>,
+    pub strict: bool,
+}
+
+const DEFAULT_LIMIT: usize = 1_000_000;
+
+fn numbers(text: &str, line: usize) -> Result<Vec<i64>, String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|n| !n.is_empty())
+        .map(|n| n.parse::<i64>().map_err(|_| format!("line {}: bad number {}", line, n)))
+        .collect()
+}
+
+fn cell(text: &str, line: usize) -> Result<(usize, i64), String> {
+    let bad = || format!("line {}: bad memory cell {}", line, text);
+    let mut parts = text.splitn(2, '=');
+    let addr = parts.next().and_then(|a| a.parse::<usize>().ok()).ok_or_else(bad)?;
+    let value = parts.next().and_then(|v| v.parse::<i64>().ok()).ok_or_else(bad)?;
+
+    Ok((addr, value))
+}
+
+pub fn parse(text: &str) -> Result<Vec<Case>, String> {
+    let mut cases = Vec::<Case>::new();
+
+    for (n, line) in text.lines().enumerate().map(|(n, l)| (n + 1, l.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, rest) = match line.find(char::is_whitespace) {
+            Some(at) => (&line[..at], line[at..].trim()),
+            None => (line, ""),
+        };
+
+        if key == "test" {
+            cases.push(Case { name: rest.to_string(), ..Default::default() });
+            continue;
+        }
+
+        let case = cases.last_mut().ok_or_else(|| format!("line {}: {} before any test", n, key))?;
+
+        match key {
+            "program" => case.program = numbers(rest, n)?,
+            "input" => case.input.extend(numbers(rest, n)?),
+            "output" => case.output.get_or_insert_with(Vec::new).extend(numbers(rest, n)?),
+            "memory" => {
+                for c in rest.split_whitespace() {
+                    case.memory.push(cell(c, n)?);
+                }
+            },
+            "error" => case.error = Some(rest.to_string()),
+            "strict" => case.strict = true,
+            "limit" => case.limit = Some(rest.parse().map_err(|_| format!("line {}: bad limit {}", n, rest))?),
+            _ => return Err(format!("line {}: unknown key {}", n, key)),
+        }
+    }
+
+    match cases.iter().find(|c| c.program.is_empty()) {
+        Some(c) => Err(format!("test {} has no program", c.name)),
+        None => Ok(cases),
+    }
+}
+
+// Loads a single case file, or every `.ict` file in a directory. Case names are
+// prefixed with the file they came from.
+pub fn load(path: &Path) -> io::Result<Vec<Case>> {
+    let mut files = if path.is_dir() {
+        fs::read_dir(path)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|p| p.extension().is_some_and(|e| e == "ict"))
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    };
+    let mut cases = Vec::new();
+
+    files.sort();
+
+    for file in files.iter() {
+        let stem = file.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+        let parsed = parse(&fs::read_to_string(file)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file.display(), e)))?;
+
+        cases.extend(parsed.into_iter().map(|c| Case { name: format!("{}::{}", stem, c.name), ..c }));
+    }
+
+    Ok(cases)
+}
+
+impl Case {
+    pub fn run(&self) -> Result<(), String> {
+        let memory = Memory::new(self.program.clone());
+        let mut intcode = IntCode::new(memory);
+        let mut input = self.input.clone();
+
+        if self.strict {
+            intcode.policy = Policy::Strict;
+        }
+
+        let result = intcode.try_run(&mut input, self.limit.unwrap_or(DEFAULT_LIMIT));
+
+        match (&result, &self.error) {
+            (Ok(output), None) => {
+                if let Some(expected) = &self.output {
+                    if output != expected {
+                        return Err(format!("expected output {:?}, got {:?}", expected, output));
+                    }
+                }
+            },
+            (Ok(_), Some(error)) => return Err(format!("expected error {}, but the program halted", error)),
+            (Err(fault), None) => return Err(fault.to_string()),
+            (Err(fault), Some(error)) => {
+                if fault.name() != error {
+                    return Err(format!("expected error {}, got {}", error, fault.name()));
+                }
+            },
+        }
+
+        for (addr, value) in self.memory.iter() {
+            let actual = intcode.mem.read(*addr);
+
+            if actual != *value {
+                return Err(format!("expected [{}] = {}, got {}", addr, value, actual));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Runs every case, returning the name and failure of each one that didn't pass.
+pub fn run_all(cases: &[Case]) -> Vec<(String, String)> {
+    cases.iter()
+        .filter_map(|c| c.run().err().map(|e| (c.name.clone(), e)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_run() {
+        let text = "
+            # comparisons
+            test equal
+            program 3,9,8,9,10,9,4,9,99,-1,8
+            input 8
+            output 1
+            memory 9=1
+
+            test wrong
+            program 104,5,99
+            output 6
+
+            test bad_opcode
+            program 42
+            error invalid-opcode
+        ";
+        let cases = parse(text).unwrap();
+
+        assert_eq!(cases.len(), 3);
+        assert_eq!(cases[0].memory, vec![(9, 1)]);
+        assert_eq!(cases[0].run(), Ok(()));
+        assert_eq!(cases[1].run(), Err(String::from("expected output [6], got [5]")));
+        assert_eq!(cases[2].run(), Ok(()));
+        assert!(parse("input 1").is_err());
+    }
+
+    #[test]
+    fn case_files() {
+        let cases = load(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/cases"))).unwrap();
+        let failures = run_all(&cases);
+
+        assert!(!cases.is_empty());
+        assert_eq!(failures, vec![]);
+    }
+}
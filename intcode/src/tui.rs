@@ -0,0 +1,452 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use super::disasm;
+use super::observer::Observer;
+use super::{Fault, IntCode, Instruction, Memory, OpCode};
+
+// Cells written within this many instructions are highlighted.
+const RECENT: usize = 32;
+
+// Remembers when each cell was last written, counted in fetched instructions.
+#[derive(Debug, Default)]
+struct Writes {
+    clock: usize,
+    last: HashMap<usize, usize>,
+    latest: Option<usize>,
+}
+
+impl Observer for Writes {
+    fn fetch(&mut self, _addr: usize, _instruction: &Instruction) {
+        self.clock += 1;
+    }
+
+    fn write(&mut self, addr: usize, _old: i64, _new: i64) {
+        self.last.insert(addr, self.clock);
+        self.latest = Some(addr);
+    }
+}
+
+impl Writes {
+    fn recent(&self, addr: usize) -> bool {
+        self.last.get(&addr).is_some_and(|at| self.clock - at < RECENT)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    Ready,
+    NeedInput,
+    Halted,
+    Faulted(String),
+}
+
+pub struct Machine {
+    pub name: String,
+    pub cpu: IntCode,
+    pub input: Vec<i64>,
+    pub output: Vec<i64>,
+    pub state: State,
+    writes: Rc<RefCell<Writes>>,
+}
+
+impl Machine {
+    pub fn new(name: &str, program: Vec<i64>, input: Vec<i64>) -> Self {
+        let mut cpu = IntCode::new(Memory::new(program));
+        let writes = cpu.observe(Writes::default());
+
+        Machine {
+            name: name.to_string(),
+            cpu,
+            input,
+            output: Vec::new(),
+            state: State::Ready,
+            writes,
+        }
+    }
+
+    fn runnable(&self) -> bool {
+        match self.state {
+            State::Ready => true,
+            State::NeedInput => !self.input.is_empty(),
+            _ => false,
+        }
+    }
+
+    // Runs one instruction and returns what it output, if anything.
+    fn step(&mut self) -> Option<i64> {
+        let mut output = Vec::new();
+
+        self.state = match self.cpu.try_step(&mut self.input, &mut output, usize::MAX) {
+            Ok(true) => State::Ready,
+            Ok(false) => State::Halted,
+            Err(Fault::NeedInput { .. }) => State::NeedInput,
+            Err(fault) => State::Faulted(fault.to_string()),
+        };
+        self.output.extend(output.iter());
+
+        output.first().copied()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    Plain,
+    Title,
+    Current,
+    Written,
+    Dim,
+}
+
+pub type Line = Vec<(String, Style)>;
+
+// One or more machines, each shown in turn. With `feedback` set the output of
+// every machine is queued as input to the next and the last feeds the first,
+// the way the day 7 amplifiers are wired.
+pub struct Visualizer {
+    pub machines: Vec<Machine>,
+    pub selected: usize,
+    pub running: bool,
+    // Instructions per machine between redraws while running.
+    pub speed: usize,
+    feedback: bool,
+    // First memory row shown, None follows the latest write.
+    memory_top: Option<usize>,
+}
+
+impl Visualizer {
+    pub fn new(machines: Vec<Machine>, feedback: bool) -> Self {
+        Visualizer {
+            machines,
+            selected: 0,
+            running: false,
+            speed: 1,
+            feedback,
+            memory_top: None,
+        }
+    }
+
+    // Steps every machine that can make progress once. False when none could.
+    pub fn tick(&mut self) -> bool {
+        let count = self.machines.len();
+        let mut moved = false;
+
+        for i in 0..count {
+            if !self.machines[i].runnable() {
+                continue;
+            }
+
+            moved = true;
+            if let Some(value) = self.machines[i].step() {
+                if self.feedback {
+                    self.machines[(i + 1) % count].input.push(value);
+                }
+            }
+        }
+
+        moved
+    }
+
+    // Runs `speed` ticks, pausing once everything has stopped.
+    pub fn advance(&mut self) {
+        for _ in 0..self.speed {
+            if !self.tick() {
+                self.running = false;
+                break;
+            }
+        }
+    }
+
+    // Handles a key press, false to quit.
+    pub fn key(&mut self, key: char) -> bool {
+        match key {
+            'q' => return false,
+            's' | ' ' => {
+                self.running = false;
+                self.tick();
+            },
+            'r' => self.running = true,
+            'p' => self.running = false,
+            '+' => self.speed = (self.speed * 2).min(1 << 20),
+            '-' => self.speed = (self.speed / 2).max(1),
+            '\t' | 'n' => self.selected = (self.selected + 1) % self.machines.len(),
+            'j' => self.memory_top = Some(self.memory_top.unwrap_or(0) + 1),
+            'k' => self.memory_top = Some(self.memory_top.unwrap_or(0).saturating_sub(1)),
+            'f' => self.memory_top = None,
+            _ => (),
+        }
+
+        true
+    }
+
+    pub fn render(&self, width: usize, height: usize) -> Vec<Line> {
+        let machine = &self.machines[self.selected];
+        let body = height.saturating_sub(6);
+        let left = (width / 2).clamp(20, 40);
+        let right = width.saturating_sub(left + 3);
+        let per_row = (right.saturating_sub(8) / 8).clamp(1, 8);
+
+        let mut lines = vec![
+            vec![(self.header(), Style::Title)],
+            vec![(String::from("─").repeat(width), Style::Dim)],
+        ];
+
+        let code = self.disassembly(body);
+        let memory = self.memory(body, per_row);
+
+        for row in 0..body {
+            let mut line = code.get(row).cloned().unwrap_or_else(|| vec![(String::new(), Style::Plain)]);
+            let used = line.iter().map(|(text, _)| text.chars().count()).sum::<usize>();
+
+            line.push((" ".repeat(left.saturating_sub(used)), Style::Plain));
+            line.push((String::from(" │ "), Style::Dim));
+            line.extend(memory.get(row).cloned().unwrap_or_default());
+            lines.push(line);
+        }
+
+        let tail = |values: &[i64]| {
+            let text = values.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(",");
+            let room = width.saturating_sub(6);
+            match text.chars().count() > room {
+                true => format!("…{}", text.chars().skip(text.chars().count() + 1 - room).collect::<String>()),
+                false => text,
+            }
+        };
+
+        lines.push(vec![(String::from("─").repeat(width), Style::Dim)]);
+        lines.push(vec![(format!("in:  {}", machine.input.iter().take(width).map(|v| v.to_string()).collect::<Vec<String>>().join(",")), Style::Plain)]);
+        lines.push(vec![(format!("out: {}", tail(&machine.output)), Style::Plain)]);
+        lines.push(vec![(String::from("s step  r run  p pause  +/- speed  tab machine  j/k scroll  f follow  q quit"), Style::Dim)]);
+
+        lines.into_iter().map(|line| fit(line, width)).collect()
+    }
+
+    fn header(&self) -> String {
+        let machine = &self.machines[self.selected];
+        let tabs = match self.machines.len() {
+            1 => String::new(),
+            _ => self.machines.iter().enumerate()
+                .map(|(i, m)| if i == self.selected { format!("[{}]", m.name) } else { format!(" {} ", m.name) })
+                .collect::<String>() + "  ",
+        };
+        let state = match &machine.state {
+            State::Ready if self.running => String::from("running"),
+            State::Ready => String::from("paused"),
+            State::NeedInput => String::from("waiting for input"),
+            State::Halted => String::from("halted"),
+            State::Faulted(fault) => fault.clone(),
+        };
+
+        format!(
+            "{}{}  step {}  ic {}  rb {}  x{}",
+            tabs, state, machine.cpu.steps, machine.cpu.ic, machine.cpu.relative_base, self.speed
+        )
+    }
+
+    // A third of the pane comes from a linear sweep before `ic`, the rest is
+    // decoded from `ic` itself so the current instruction is always aligned.
+    fn disassembly(&self, rows: usize) -> Vec<Line> {
+        let cpu = &self.machines[self.selected].cpu;
+        let program = cpu.mem.snapshot();
+        let show = |addr: usize, i: &Instruction, style: Style| {
+            let marker = if addr == cpu.ic { ">" } else { " " };
+            let text = match i.op {
+                OpCode::Unknown => format!("{}{:>6}: data {}", marker, addr, disasm::word(&program, addr)),
+                _ => format!("{}{:>6}: {}", marker, addr, i),
+            };
+            vec![(text, style)]
+        };
+
+        let before = disasm::disassemble(&program).into_iter()
+            .filter(|(addr, i)| addr + i.len.max(1) <= cpu.ic)
+            .collect::<Vec<(usize, Instruction)>>();
+        let mut lines = before[before.len().saturating_sub(rows / 3)..].iter()
+            .map(|(addr, i)| show(*addr, i, Style::Dim))
+            .collect::<Vec<Line>>();
+        let mut addr = cpu.ic;
+
+        while lines.len() < rows {
+            let i = disasm::decode(&program, addr);
+            let style = if addr == cpu.ic { Style::Current } else { Style::Plain };
+
+            lines.push(show(addr, &i, style));
+            addr += i.len.max(1);
+        }
+
+        lines
+    }
+
+    fn memory(&self, rows: usize, per_row: usize) -> Vec<Line> {
+        let machine = &self.machines[self.selected];
+        let writes = machine.writes.borrow();
+        let size = machine.cpu.mem.snapshot().len();
+        let last_row = size.saturating_sub(1) / per_row;
+        let top = match self.memory_top {
+            Some(top) => top.min(last_row),
+            None => (writes.latest.unwrap_or(machine.cpu.ic) / per_row).saturating_sub(rows / 2),
+        };
+
+        (top..=last_row).take(rows)
+            .map(|row| {
+                let start = row * per_row;
+                let mut line = vec![(format!("{:>6}:", start), Style::Dim)];
+
+                for addr in (start..start + per_row).take_while(|a| *a < size) {
+                    let style = match addr {
+                        _ if writes.recent(addr) => Style::Written,
+                        _ if addr == machine.cpu.ic => Style::Current,
+                        _ => Style::Plain,
+                    };
+                    line.push((format!("{:>8}", machine.cpu.mem.peek(addr)), style));
+                }
+
+                line
+            })
+            .collect()
+    }
+}
+
+// Cuts a line down to `width` characters.
+fn fit(line: Line, width: usize) -> Line {
+    let mut room = width;
+
+    line.into_iter()
+        .map(|(text, style)| {
+            let cut = text.chars().take(room).collect::<String>();
+            room -= cut.chars().count();
+            (cut, style)
+        })
+        .filter(|(text, _)| !text.is_empty())
+        .collect()
+}
+
+fn draw(out: &mut impl Write, lines: &[Line]) -> io::Result<()> {
+    for (row, line) in lines.iter().enumerate() {
+        queue!(out, MoveTo(0, row as u16), Clear(ClearType::CurrentLine))?;
+
+        for (text, style) in line.iter() {
+            match style {
+                Style::Plain => (),
+                Style::Title => queue!(out, SetAttribute(Attribute::Reverse))?,
+                Style::Current => queue!(out, SetForegroundColor(Color::Yellow), SetAttribute(Attribute::Bold))?,
+                Style::Written => queue!(out, SetForegroundColor(Color::Green))?,
+                Style::Dim => queue!(out, SetForegroundColor(Color::DarkGrey))?,
+            }
+            queue!(out, Print(text), SetAttribute(Attribute::Reset), ResetColor)?;
+        }
+    }
+
+    out.flush()
+}
+
+// Takes over the terminal until `q` is pressed.
+pub fn show(visualizer: &mut Visualizer) -> io::Result<()> {
+    let mut out = io::stdout();
+
+    terminal::enable_raw_mode()?;
+    execute!(out, EnterAlternateScreen, Hide)?;
+
+    let result = event_loop(visualizer, &mut out);
+
+    execute!(out, Show, LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+
+    result
+}
+
+fn event_loop(visualizer: &mut Visualizer, out: &mut impl Write) -> io::Result<()> {
+    loop {
+        let (width, height) = terminal::size()?;
+        draw(out, &visualizer.render(width as usize, height as usize))?;
+
+        let wait = if visualizer.running { Duration::from_millis(20) } else { Duration::from_secs(1) };
+
+        if event::poll(wait)? {
+            if let Event::Key(key) = event::read()? {
+                let c = match key.code {
+                    KeyCode::Char(c) => c,
+                    KeyCode::Tab => '\t',
+                    KeyCode::Esc => 'q',
+                    _ => continue,
+                };
+
+                if key.kind == KeyEventKind::Press && !visualizer.key(c) {
+                    return Ok(());
+                }
+            }
+        }
+
+        if visualizer.running {
+            visualizer.advance();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(line: &Line) -> String {
+        line.iter().map(|(t, _)| t.as_str()).collect()
+    }
+
+    #[test]
+    fn steps_and_renders() {
+        // Doubles its input into cell 9 and prints it.
+        let program = vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0];
+        let mut visualizer = Visualizer::new(vec![Machine::new("a", program, vec![21])], false);
+
+        assert!(visualizer.key('s'));
+        assert!(visualizer.key('s'));
+
+        let lines = visualizer.render(80, 12);
+
+        assert_eq!(lines.len(), 12);
+        assert!(lines.iter().all(|l| text(l).chars().count() <= 80));
+        assert_eq!(text(&lines[0]), "paused  step 2  ic 6  rb 0  x1");
+        assert!(lines.iter().any(|l| l.contains(&(String::from(">     6: out [9]"), Style::Current))));
+        assert!(lines.iter().any(|l| l.contains(&(format!("{:>8}", 42), Style::Written))));
+        assert_eq!(text(&lines[10]), "out: ");
+
+        visualizer.key('r');
+        visualizer.speed = 100;
+        visualizer.advance();
+
+        assert!(!visualizer.running);
+        assert_eq!(visualizer.machines[0].state, State::Halted);
+        assert_eq!(visualizer.machines[0].output, vec![42]);
+        assert!(!visualizer.key('q'));
+    }
+
+    #[test]
+    fn feedback_loop_matches_day7() {
+        let program = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1, 28, 1005, 28, 6,
+            99, 0, 0, 5,
+        ];
+        let machines = [9, 8, 7, 6, 5].iter().enumerate()
+            .map(|(i, phase)| {
+                let input = if i == 0 { vec![*phase, 0] } else { vec![*phase] };
+                Machine::new(&((b'A' + i as u8) as char).to_string(), program.clone(), input)
+            })
+            .collect();
+        let mut visualizer = Visualizer::new(machines, true);
+
+        while visualizer.tick() {}
+
+        assert!(visualizer.machines.iter().all(|m| m.state == State::Halted));
+        assert_eq!(visualizer.machines[0].input, vec![139629729]);
+
+        visualizer.key('\t');
+        assert!(text(&visualizer.render(80, 10)[0]).starts_with(" A [B] C  D  E   halted"));
+    }
+}